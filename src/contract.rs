@@ -1,523 +1,4456 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Addr, Binary, CosmosMsg, Decimal, Deps, DepsMut, Env, Event, MessageInfo,
-    QuerierWrapper, Reply, Response, StdResult, Storage, SubMsg,
+    coin, to_json_binary, Addr, Api, Binary, Coin, CosmosMsg, Decimal, Deps, DepsMut, Env, Event,
+    MessageInfo, QuerierWrapper, Reply, Response, StdError, StdResult, Storage, SubMsg,
+    SubMsgResult, Uint128, WasmMsg,
 };
-use kujira::Denom;
+use cw_utils::Duration;
+use kujira::{Denom, KujiraQuerier, KujiraQuery};
+use semver::Version;
 
 use crate::error::ContractError;
 use crate::msg::{
-    ActionResponse, ActionsResponse, ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
-    StatusResponse,
+    ActionResponse, ActionsResponse, ConfigResponse, DistributionResponse, DistributionsResponse,
+    ExecuteMsg, FailuresResponse, HistoryResponse, InputStatsResponse, InstantiateMsg, MigrateMsg,
+    PendingOwnerResponse, QueryMsg, RewardsResponse, SimulateRunResponse, SimulatedActionResponse,
+    StatusResponse, TargetAddressesUpdate, TargetDenomsUpdate, TargetStatsResponse,
+};
+use crate::state::{
+    migrate_v0_1, Action, ActionFailures, ActionUsage, Config, Distribution, ExecutorReward,
+    InputStats, LastRun, MinReturn, OracleAsset, Pending, PendingOwner, RunRecord, Schedule,
+    SwapRecord, TargetStats, MAX_BATCH_SIZE,
 };
-use crate::state::{Action, Config};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:kujira-revenue-converter";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// pagination defaults for list queries
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, msg: InstantiateMsg) -> Result<Response, ContractError> {
+pub fn migrate(
+    deps: DepsMut<KujiraQuery>,
+    _env: Env,
+    msg: MigrateMsg,
+) -> Result<Response, ContractError> {
+    let stored = cw2::get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::InvalidContract {
+            name: stored.contract,
+        });
+    }
+    let version: Version = stored.version.parse()?;
+    let current: Version = CONTRACT_VERSION.parse()?;
+    if version > current {
+        return Err(ContractError::InvalidVersion {
+            stored: stored.version,
+            current: CONTRACT_VERSION.to_string(),
+        });
+    }
+
+    if version < Version::new(0, 2, 0) {
+        migrate_v0_1(deps.storage, deps.api)?;
+    }
     cw2::set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    let config = Config::from(msg);
-    config.save(deps.storage)?;
-    Ok(Response::default())
+
+    match msg {
+        MigrateMsg::Upgrade {
+            owner,
+            executors,
+            target_denoms,
+            target_addresses,
+            batch_size,
+        } => {
+            let mut config = Config::load(deps.storage)?;
+            if let Some(owner) = owner {
+                config.owner = validate_addr(deps.api, "owner", &owner)?;
+            }
+            if let Some(executors) = executors {
+                config.executors = validate_executors(deps.api, executors)?;
+            }
+            if let Some(target_denoms) = target_denoms {
+                config.target_denoms = target_denoms;
+            }
+            if let Some(target_addresses) = target_addresses {
+                config.target_addresses = validate_targets(deps.api, target_addresses)?;
+            }
+            if let Some(batch_size) = batch_size {
+                config.batch_size = batch_size;
+            }
+            config.validate()?;
+            config.save(deps.storage)?;
+        }
+    }
+
+    let event = Event::new("revenue/migrate")
+        .add_attribute("from", stored.version)
+        .add_attribute("to", CONTRACT_VERSION);
+    Ok(Response::default().add_event(event))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    deps: DepsMut,
+    deps: DepsMut<KujiraQuery>,
     _env: Env,
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     cw2::set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    let config = Config::from(msg);
+    let config = Config {
+        owner: validate_addr(deps.api, "owner", &msg.owner)?,
+        executors: validate_executors(deps.api, msg.executors)?,
+        guardian: None,
+        permissionless: None,
+        executor_reward: None,
+        target_denoms: msg.target_denoms,
+        target_addresses: validate_targets(deps.api, msg.target_addresses)?,
+        batch_size: msg.batch_size.unwrap_or(1),
+        max_failures: None,
+        paused: false,
+        schedule: Schedule::Rotation,
+    };
+    config.validate()?;
     config.save(deps.storage)?;
     Ok(Response::default())
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
+    deps: DepsMut<KujiraQuery>,
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     let mut config = Config::load(deps.storage)?;
     match msg {
-        ExecuteMsg::SetOwner(owner) => {
+        ExecuteMsg::ProposeOwner { owner, expiry } => {
             if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
+            if expiry.is_some_and(|x| x.is_expired(&env.block)) {
+                return Err(ContractError::OwnershipProposalExpired {});
+            }
+
+            PendingOwner {
+                owner: validate_addr(deps.api, "owner", &owner)?,
+                expiry,
+            }
+            .save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::AcceptOwner {} => {
+            let pending =
+                PendingOwner::load(deps.storage)?.ok_or(ContractError::NoPendingOwner {})?;
+            if info.sender != pending.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            if pending.expiry.is_some_and(|x| x.is_expired(&env.block)) {
+                return Err(ContractError::OwnershipProposalExpired {});
+            }
 
-            config.owner = owner;
+            PendingOwner::clear(deps.storage);
+            config.owner = pending.owner;
             config.save(deps.storage)?;
             Ok(Response::default())
         }
+        ExecuteMsg::CancelOwner {} => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            if PendingOwner::load(deps.storage)?.is_none() {
+                return Err(ContractError::NoPendingOwner {});
+            }
+
+            PendingOwner::clear(deps.storage);
+            Ok(Response::default())
+        }
         ExecuteMsg::SetAction(action) => {
             if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
 
+            let mut action = action;
+            action.contract = validate_addr(deps.api, "contract", &action.contract)?.to_string();
+            for (idx, hop) in action.hops.iter_mut().enumerate() {
+                let field = format!("hops[{idx}].contract");
+                hop.contract = validate_addr(deps.api, &field, &hop.contract)?.to_string();
+            }
+            if action.max_fraction.is_some_and(|x| x > Decimal::one()) {
+                return Err(ContractError::InvalidFraction {});
+            }
+            if action.weight == 0 {
+                return Err(ContractError::InvalidWeight {});
+            }
+            action.validate_msgs()?;
+            if let Some(MinReturn::Oracle { offer, ask, .. }) = &action.min_return {
+                OracleAsset::rate(&KujiraQuerier::new(&deps.querier), offer, ask)?;
+            }
+            ActionFailures::reset(deps.storage, &action)?;
             Action::set(deps.storage, action)?;
             Ok(Response::default())
         }
-        ExecuteMsg::UnsetAction(denom) => {
+        ExecuteMsg::UnsetAction { denom, priority } => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            for action in Action::select(deps.storage, &denom, priority)? {
+                Action::unset(deps.storage, &action)?;
+            }
+            Ok(Response::default())
+        }
+        ExecuteMsg::PauseAction { denom, priority } => {
             if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
 
-            Action::unset(deps.storage, denom);
+            for mut action in Action::select(deps.storage, &denom, priority)? {
+                action.enabled = false;
+                Action::set(deps.storage, action)?;
+            }
+            Ok(Response::default())
+        }
+        ExecuteMsg::ResumeAction { denom, priority } => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            for mut action in Action::select(deps.storage, &denom, priority)? {
+                action.enabled = true;
+                ActionFailures::reset(deps.storage, &action)?;
+                Action::set(deps.storage, action)?;
+            }
+            Ok(Response::default())
+        }
+        ExecuteMsg::SetGuardian(guardian) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            config.guardian = guardian
+                .map(|x| validate_addr(deps.api, "guardian", &x))
+                .transpose()?;
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::Pause {} => {
+            if info.sender != config.owner && config.guardian.as_ref() != Some(&info.sender) {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            config.paused = true;
+            config.save(deps.storage)?;
             Ok(Response::default())
         }
-        ExecuteMsg::SetExecutor(executor) => {
+        ExecuteMsg::Resume {} => {
             if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
 
-            config.executor = executor;
+            config.paused = false;
             config.save(deps.storage)?;
             Ok(Response::default())
         }
-        ExecuteMsg::Run {} => {
-            if info.sender != config.executor {
+        ExecuteMsg::Sweep { recipient, denoms } => {
+            if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
-            let action_msg = get_action_msg(deps.storage, deps.querier, &env.contract.address)?;
+            if !config.paused {
+                return Err(ContractError::NotPaused {});
+            }
 
-            match action_msg {
-                Some((action, msg)) => {
-                    let event =
-                        Event::new("revenue/run").add_attribute("denom", action.denom.to_string());
-                    Ok(Response::default()
-                        .add_event(event)
-                        .add_submessage(SubMsg::reply_always(msg, 0)))
+            let recipient = validate_addr(deps.api, "recipient", &recipient)?;
+            let mut event =
+                Event::new("revenue/sweep").add_attribute("recipient", recipient.to_string());
+            let mut sends: Vec<CosmosMsg> = vec![];
+            for denom in denoms {
+                let amount = balance(&deps.querier, &env, denom.to_string())?;
+                if !amount.is_zero() {
+                    sends.push(denom.send(&recipient, &amount));
+                    event = event.add_attribute("amount", denom.coin(&amount).to_string());
                 }
-                // If there's no compatible action, skip to the reply
+            }
+            Ok(Response::default().add_event(event).add_messages(sends))
+        }
+        ExecuteMsg::AddExecutor(executor) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            let executor = validate_addr(deps.api, "executor", &executor)?;
+            if config.executors.contains(&executor) {
+                return Err(ContractError::ExecutorExists {
+                    executor: executor.to_string(),
+                });
+            }
+            config.executors.push(executor);
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::RemoveExecutor(executor) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            let executor = validate_addr(deps.api, "executor", &executor)?;
+            match config.executors.iter().position(|x| x == executor) {
+                Some(idx) => config.executors.remove(idx),
                 None => {
-                    let mut sends: Vec<CosmosMsg> = vec![];
-                    for target in config.target_denoms.clone() {
-                        distribute_denom(deps.as_ref(), &env, &config, &mut sends, target)?;
+                    return Err(ContractError::ExecutorNotFound {
+                        executor: executor.to_string(),
+                    })
+                }
+            };
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::SetPermissionless(interval) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            if matches!(interval, Some(Duration::Height(0) | Duration::Time(0))) {
+                return Err(ContractError::InvalidInterval {});
+            }
+
+            config.permissionless = interval;
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::SetExecutorReward(reward) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            if reward.as_ref().is_some_and(|x| x.bps > 10000) {
+                return Err(ContractError::InvalidReward {});
+            }
+
+            config.executor_reward = reward;
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::SetMaxFailures(max_failures) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            config.max_failures = max_failures;
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::SetSchedule(schedule) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            config.schedule = schedule;
+            config.save(deps.storage)?;
+            Ok(Response::default())
+        }
+        ExecuteMsg::Run { max_actions } => {
+            if config.paused {
+                return Err(ContractError::Paused {});
+            }
+            if max_actions == Some(0) {
+                return Err(ContractError::InvalidBatchSize {
+                    max: MAX_BATCH_SIZE,
+                });
+            }
+            if !config.executors.contains(&info.sender) {
+                // Anyone else may only crank when permissionless, and not before the interval
+                let interval = config
+                    .permissionless
+                    .ok_or(ContractError::Unauthorized {})?;
+                if let Some(last) = LastRun::load(deps.storage)? {
+                    if !last.elapsed(&env.block, &interval) {
+                        return Err(ContractError::RateLimited {});
+                    }
+                }
+            }
+            LastRun::save(deps.storage, &env.block)?;
+            let limit = max_actions.map_or(config.batch_size, |x| x.min(config.batch_size));
+            let actions = Action::next(deps.storage, limit as usize, &config.schedule)?;
+            execute_queue(deps, &env, RunRecord::new(&env.block, info.sender), actions)
+        }
+        ExecuteMsg::RunDenom(denom) => {
+            if !config.executors.contains(&info.sender) {
+                return Err(ContractError::Unauthorized {});
+            }
+            if config.paused {
+                return Err(ContractError::Paused {});
+            }
+            let action = Action::select(deps.storage, &denom, None)?
+                .into_iter()
+                .find(|x| x.enabled)
+                .ok_or(ContractError::ActionPaused {
+                    denom: denom.to_string(),
+                })?;
+            let run = RunRecord::new(&env.block, info.sender);
+            execute_queue(deps, &env, run, vec![action])
+        }
+        ExecuteMsg::UpdateTargetDenoms(update) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            let mut updated = config.clone();
+            match update {
+                TargetDenomsUpdate::Add(denoms) => updated.target_denoms.extend(denoms),
+                TargetDenomsUpdate::Remove(denoms) => {
+                    for denom in denoms {
+                        match updated.target_denoms.iter().position(|x| x == &denom) {
+                            Some(idx) => updated.target_denoms.remove(idx),
+                            None => {
+                                return Err(ContractError::TargetNotFound {
+                                    target: denom.to_string(),
+                                })
+                            }
+                        };
                     }
+                }
+                TargetDenomsUpdate::Replace(denoms) => updated.target_denoms = denoms,
+            }
+            updated.validate()?;
+            updated.save(deps.storage)?;
+            Ok(Response::default().add_event(config_diff(&config, &updated)))
+        }
+        ExecuteMsg::UpdateTargetAddresses(update) => {
+            if info.sender != config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
 
-                    Ok(Response::default().add_messages(sends))
+            let mut updated = config.clone();
+            match update {
+                TargetAddressesUpdate::Add(targets) => {
+                    let targets = validate_targets(deps.api, targets)?;
+                    updated.target_addresses.extend(targets);
+                }
+                TargetAddressesUpdate::Remove(addrs) => {
+                    for addr in addrs {
+                        let addr = validate_addr(deps.api, "target_addresses", &addr)?;
+                        match updated.target_addresses.iter().position(|(x, _)| x == addr) {
+                            Some(idx) => updated.target_addresses.remove(idx),
+                            None => {
+                                return Err(ContractError::TargetNotFound {
+                                    target: addr.to_string(),
+                                })
+                            }
+                        };
+                    }
+                }
+                TargetAddressesUpdate::Replace(targets) => {
+                    updated.target_addresses = validate_targets(deps.api, targets)?;
                 }
             }
+            updated.validate()?;
+            updated.save(deps.storage)?;
+            Ok(Response::default().add_event(config_diff(&config, &updated)))
+        }
+        ExecuteMsg::GuardedSwap {
+            msg,
+            min_return,
+            before,
+        } => {
+            if info.sender != env.contract.address {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            let check = WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                msg: to_json_binary(&ExecuteMsg::CheckReturn { min_return, before })?,
+                funds: vec![],
+            };
+            Ok(Response::default().add_message(msg).add_message(check))
+        }
+        ExecuteMsg::CheckReturn { min_return, before } => {
+            if info.sender != env.contract.address {
+                return Err(ContractError::Unauthorized {});
+            }
+
+            check_return(&deps.querier, &env, &min_return, before)?;
+            Ok(Response::default())
         }
     }
 }
 
-fn get_action_msg(
-    storage: &mut dyn Storage,
-    querier: QuerierWrapper,
-    contract: &Addr,
-) -> StdResult<Option<(Action, CosmosMsg)>> {
-    // Fetch the next action in the iterator
-    if let Some(action) = Action::next(storage)? {
-        let balance = querier.query_balance(contract, action.denom.to_string())?;
-        return match action.execute(balance)? {
-            None => Ok(None),
-            Some(msg) => Ok(Some((action, msg))),
+pub(crate) fn validate_addr(api: &dyn Api, field: &str, addr: &str) -> Result<Addr, ContractError> {
+    api.addr_validate(addr)
+        .map_err(|_| ContractError::InvalidAddress {
+            field: field.to_string(),
+            address: addr.to_string(),
+        })
+}
+
+fn validate_executors(api: &dyn Api, executors: Vec<String>) -> Result<Vec<Addr>, ContractError> {
+    let mut validated: Vec<Addr> = vec![];
+    for executor in executors {
+        let executor = validate_addr(api, "executors", &executor)?;
+        if validated.contains(&executor) {
+            return Err(ContractError::ExecutorExists {
+                executor: executor.to_string(),
+            });
+        }
+        validated.push(executor);
+    }
+    Ok(validated)
+}
+
+fn validate_targets(
+    api: &dyn Api,
+    targets: Vec<(String, u8)>,
+) -> Result<Vec<(Addr, u8)>, ContractError> {
+    targets
+        .into_iter()
+        .map(|(addr, weight)| Ok((validate_addr(api, "target_addresses", &addr)?, weight)))
+        .collect()
+}
+
+/// Describes the target denoms and addresses added and removed by a Config update.
+/// A change of weight is reported as the removal of the previous entry and addition of the new one
+fn config_diff(old: &Config, new: &Config) -> Event {
+    let mut event = Event::new("revenue/config");
+    for denom in new.target_denoms.iter() {
+        if !old.target_denoms.contains(denom) {
+            event = event.add_attribute("denom_added", denom.to_string());
+        }
+    }
+    for denom in old.target_denoms.iter() {
+        if !new.target_denoms.contains(denom) {
+            event = event.add_attribute("denom_removed", denom.to_string());
+        }
+    }
+    for target in new.target_addresses.iter() {
+        if !old.target_addresses.contains(target) {
+            event = event.add_attribute("address_added", format!("{}:{}", target.0, target.1));
+        }
+    }
+    for target in old.target_addresses.iter() {
+        if !new.target_addresses.contains(target) {
+            event = event.add_attribute("address_removed", format!("{}:{}", target.0, target.1));
+        }
+    }
+    event
+}
+
+/// Dispatches the first queued Action that has a balance to swap, leaving the rest of the
+/// queue to its reply. Once the queue is exhausted, the target denoms are distributed
+fn execute_queue(
+    deps: DepsMut<KujiraQuery>,
+    env: &Env,
+    mut run: RunRecord,
+    mut queue: Vec<Action>,
+) -> Result<Response, ContractError> {
+    let config = Config::load(deps.storage)?;
+    let querier = KujiraQuerier::new(&deps.querier);
+    let mut events = vec![];
+    while !queue.is_empty() {
+        let action = queue.remove(0);
+        let amount = deps
+            .querier
+            .query_balance(&env.contract.address, action.denom.to_string())?;
+        let allowance = ActionUsage::allowance(deps.storage, &action, &env.block)?;
+        let offer = action.offer(amount.amount, allowance);
+        let executed = match action.execute(&querier, &env.contract.address, amount, allowance) {
+            Ok(executed) => executed,
+            Err(err) => {
+                // A pricing error skips the Action, rather than failing the whole Run
+                let error = err.to_string();
+                let event = record_failure(deps.storage, &config, &action, &error)?;
+                events.push(queue_fallback(deps.storage, &action, &mut queue, event)?);
+                run.swaps.push(SwapRecord {
+                    denom: action.denom,
+                    contract: Addr::unchecked(action.contract),
+                    amount: Uint128::zero(),
+                    output: vec![],
+                    error: Some(error),
+                });
+                continue;
+            }
         };
+        if let Some((msg, min_return)) = executed {
+            let min_return = match min_return {
+                Some(min_return) => {
+                    let before = balance(&deps.querier, env, &min_return.denom)?;
+                    Some((min_return, before))
+                }
+                None => None,
+            };
+            let (balance, msg) = match action.hops.first() {
+                Some(hop) => (balance(&deps.querier, env, hop.denom.to_string())?, msg),
+                None => (Uint128::zero(), guard(env, msg, &min_return)?),
+            };
+            let outputs = balances(&deps.querier, env, &config.target_denoms)?;
+            let event = Event::new("revenue/run").add_attribute("denom", action.denom.to_string());
+            Pending {
+                action,
+                offer,
+                hop: 0,
+                balance,
+                min_return,
+                outputs,
+                queue,
+                run,
+            }
+            .save(deps.storage)?;
+            return Ok(Response::default()
+                .add_events(events)
+                .add_event(event)
+                .add_submessage(SubMsg::reply_always(msg, 0)));
+        }
     }
-    Ok(None)
+
+    // If there's no compatible action, skip to the distribution
+    let mut res = execute_reply(deps, env.clone(), run)?;
+    res.events.splice(0..0, events);
+    Ok(res)
+}
+
+/// Wraps the final swap of a route with a minimum return in a self-call that reverts it on a
+/// shortfall, so that the reply handles the shortfall as a failed swap rather than reverting
+/// the whole Run
+fn guard(env: &Env, msg: CosmosMsg, min_return: &Option<(Coin, Uint128)>) -> StdResult<CosmosMsg> {
+    match min_return {
+        Some((min_return, before)) => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_json_binary(&ExecuteMsg::GuardedSwap {
+                msg,
+                min_return: min_return.clone(),
+                before: *before,
+            })?,
+            funds: vec![],
+        })),
+        None => Ok(msg),
+    }
+}
+
+/// Fails if the contract received less than `min_return` since its balance was `before`
+fn check_return(
+    querier: &QuerierWrapper<KujiraQuery>,
+    env: &Env,
+    min_return: &Coin,
+    before: Uint128,
+) -> Result<(), ContractError> {
+    let received = balance(querier, env, &min_return.denom)?.saturating_sub(before);
+    if received < min_return.amount {
+        return Err(ContractError::InsufficientReturn {
+            received: coin(received.u128(), &min_return.denom),
+            expected: min_return.clone(),
+        });
+    }
+    Ok(())
+}
+
+fn balance(
+    querier: &QuerierWrapper<KujiraQuery>,
+    env: &Env,
+    denom: impl Into<String>,
+) -> StdResult<Uint128> {
+    Ok(querier.query_balance(&env.contract.address, denom)?.amount)
+}
+
+fn balances(
+    querier: &QuerierWrapper<KujiraQuery>,
+    env: &Env,
+    denoms: &[Denom],
+) -> StdResult<Vec<Coin>> {
+    denoms
+        .iter()
+        .map(|denom| querier.query_balance(&env.contract.address, denom.to_string()))
+        .collect()
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(deps: DepsMut, env: Env, _msg: Reply) -> Result<Response, ContractError> {
-    execute_reply(deps.as_ref(), env)
+pub fn reply(
+    mut deps: DepsMut<KujiraQuery>,
+    env: Env,
+    msg: Reply,
+) -> Result<Response, ContractError> {
+    let mut pending = Pending::take(deps.storage)?.ok_or(StdError::not_found("Pending"))?;
+    if let SubMsgResult::Err(error) = msg.result {
+        // Once a hop has been dispatched, the first swap of the route has spent its input
+        let spent = pending.hop > 0;
+        return execute_failure(deps, &env, pending, error, spent);
+    }
+    match execute_hop(deps.branch(), &env, pending.clone()) {
+        Ok(Some(res)) => Ok(res),
+        Err(err @ ContractError::InsufficientReturn { .. }) => {
+            execute_failure(deps, &env, pending, err.to_string(), true)
+        }
+        Err(err) => Err(err),
+        Ok(None) => {
+            InputStats::record(
+                deps.storage,
+                &pending.action.denom,
+                pending.offer,
+                &env.block,
+            )?;
+            ActionFailures::reset(deps.storage, &pending.action)?;
+            ActionUsage::record(deps.storage, &pending.action, pending.offer, &env.block)?;
+            let mut output = vec![];
+            for before in pending.outputs.iter() {
+                let received =
+                    balance(&deps.querier, &env, &before.denom)?.saturating_sub(before.amount);
+                if !received.is_zero() {
+                    output.push(coin(received.u128(), &before.denom));
+                }
+            }
+            pending.run.swaps.push(SwapRecord {
+                denom: pending.action.denom,
+                contract: Addr::unchecked(pending.action.contract),
+                amount: pending.offer,
+                output,
+                error: None,
+            });
+            execute_queue(deps, &env, pending.run, pending.queue)
+        }
+    }
+}
+
+/// Records the failed swap of the pending route, disabling its Action once it has failed
+/// too many times in a row, and continues with the rest of the queue. If the first swap of the
+/// route was reverted, the next route for its denom is tried first. If it had already `spent`
+/// its input, that input is recorded instead, and no other route is tried for the denom
+fn execute_failure(
+    deps: DepsMut<KujiraQuery>,
+    env: &Env,
+    mut pending: Pending,
+    error: String,
+    spent: bool,
+) -> Result<Response, ContractError> {
+    let config = Config::load(deps.storage)?;
+    let action = pending.action;
+    let mut event = record_failure(deps.storage, &config, &action, &error)?;
+
+    let mut queue = pending.queue;
+    if spent {
+        InputStats::record(deps.storage, &action.denom, pending.offer, &env.block)?;
+        ActionUsage::record(deps.storage, &action, pending.offer, &env.block)?;
+    } else {
+        // The swap was reverted, so the next route for the denom can offer the same balance
+        event = queue_fallback(deps.storage, &action, &mut queue, event)?;
+    }
+
+    pending.run.swaps.push(SwapRecord {
+        denom: action.denom,
+        contract: Addr::unchecked(action.contract),
+        amount: pending.offer,
+        output: vec![],
+        error: Some(error),
+    });
+    let mut res = execute_queue(deps, env, pending.run, queue)?;
+    res.events.insert(0, event);
+    Ok(res)
+}
+
+/// Records a failed swap of `action`, disabling it once it has failed too many times in a row
+fn record_failure(
+    storage: &mut dyn Storage,
+    config: &Config,
+    action: &Action,
+    error: &str,
+) -> StdResult<Event> {
+    let failures = ActionFailures::record(storage, action)?;
+    let mut event = Event::new("revenue/failure")
+        .add_attribute("denom", action.denom.to_string())
+        .add_attribute("error", error)
+        .add_attribute("consecutive", failures.consecutive.to_string());
+    if config
+        .max_failures
+        .is_some_and(|max| failures.consecutive >= max)
+    {
+        let mut action = action.clone();
+        action.enabled = false;
+        Action::set(storage, action)?;
+        event = event.add_attribute("disabled", "true");
+    }
+    Ok(event)
+}
+
+/// Queues the next route for the denom of the failed `action`, if any, ahead of the rest
+fn queue_fallback(
+    storage: &dyn Storage,
+    action: &Action,
+    queue: &mut Vec<Action>,
+    event: Event,
+) -> StdResult<Event> {
+    match action.fallback(storage)? {
+        Some(fallback) => {
+            let event = event.add_attribute("fallback", fallback.priority.to_string());
+            queue.insert(0, fallback);
+            Ok(event)
+        }
+        None => Ok(event),
+    }
 }
 
-pub fn execute_reply(deps: Deps, env: Env) -> Result<Response, ContractError> {
+/// Dispatches the next hop of the pending route, offering it the output of the previous swap.
+/// The final swap of the route is guarded by the Action's minimum return, which is checked
+/// again once the route is complete, in case it ended early
+fn execute_hop(
+    deps: DepsMut<KujiraQuery>,
+    env: &Env,
+    mut pending: Pending,
+) -> Result<Option<Response>, ContractError> {
+    if let Some(hop) = pending.action.hops.get(pending.hop).cloned() {
+        let received =
+            balance(&deps.querier, env, hop.denom.to_string())?.saturating_sub(pending.balance);
+        // An empty swap ends the route early, as there is nothing to offer the next hop
+        if !received.is_zero() {
+            pending.hop += 1;
+            pending.balance = match pending.action.hops.get(pending.hop) {
+                Some(next) => balance(&deps.querier, env, next.denom.to_string())?,
+                None => Uint128::zero(),
+            };
+            pending.save(deps.storage)?;
+            let mut msg = hop.execute(&env.contract.address, received)?;
+            if pending.hop == pending.action.hops.len() {
+                msg = guard(env, msg, &pending.min_return)?;
+            }
+            let event = Event::new("revenue/hop")
+                .add_attribute("denom", hop.denom.to_string())
+                .add_attribute("amount", received);
+            return Ok(Some(
+                Response::default()
+                    .add_event(event)
+                    .add_submessage(SubMsg::reply_always(msg, 0)),
+            ));
+        }
+    }
+
+    if let Some((min_return, before)) = &pending.min_return {
+        check_return(&deps.querier, env, min_return, *before)?;
+    }
+    Ok(None)
+}
+
+pub fn execute_reply(
+    deps: DepsMut<KujiraQuery>,
+    env: Env,
+    mut run: RunRecord,
+) -> Result<Response, ContractError> {
     let config = Config::load(deps.storage)?;
+    let executor = &run.executor;
     let mut sends: Vec<CosmosMsg> = vec![];
+    let mut event = Event::new("revenue/reward").add_attribute("executor", executor.to_string());
+    let mut rewarded = false;
+    let mut distributions = vec![];
     for target in config.target_denoms.clone() {
-        distribute_denom(deps, &env, &config, &mut sends, target)?;
+        let Distribution {
+            reward,
+            sends: amounts,
+            ..
+        } = distribute_denom(deps.as_ref(), &env, &config, executor, &mut sends, &target)?;
+        TargetStats::record(deps.storage, &target, &amounts)?;
+        let total = amounts.iter().fold(reward, |a, (_, amount)| a + amount);
+        if !total.is_zero() {
+            distributions.push(target.coin(&total));
+        }
+        if !reward.is_zero() {
+            ExecutorReward::record(deps.storage, &target, reward)?;
+            event = event.add_attribute("amount", target.coin(&reward).to_string());
+            rewarded = true;
+        }
     }
+    run.distributions = distributions;
+    run.save(deps.storage)?;
 
-    Ok(Response::default().add_messages(sends))
+    let res = Response::default().add_messages(sends);
+    if rewarded {
+        return Ok(res.add_event(event));
+    }
+    Ok(res)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps<KujiraQuery>, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_json_binary(&ConfigResponse::from(Config::load(deps.storage)?)),
-        QueryMsg::Actions {} => to_json_binary(&ActionsResponse {
-            actions: Action::all(deps.storage)?
-                .iter()
-                .map(|x| ActionResponse::from(x.clone()))
+        QueryMsg::Actions {
+            start_after,
+            limit,
+            contract,
+        } => {
+            let (actions, next_start_after) = Action::list(
+                deps.storage,
+                start_after,
+                limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize,
+                contract.as_deref(),
+            )?;
+            to_json_binary(&ActionsResponse {
+                actions: actions.into_iter().map(ActionResponse::from).collect(),
+                next_start_after,
+            })
+        }
+        QueryMsg::Action { denom } => to_json_binary(&ActionsResponse {
+            actions: Action::select(deps.storage, &denom, None)?
+                .into_iter()
+                .map(ActionResponse::from)
                 .collect(),
+            next_start_after: None,
         }),
         QueryMsg::Status {} => to_json_binary(&StatusResponse {
             last: Action::last(deps.storage)?.map(Denom::from),
         }),
+        QueryMsg::SimulateRun {} => to_json_binary(&simulate_run(deps, &env)?),
+        QueryMsg::Distribution { denom } => {
+            let config = Config::load(deps.storage)?;
+            to_json_binary(&DistributionsResponse {
+                distributions: distributions(deps, &env, &config, denom.as_ref())?,
+            })
+        }
+        QueryMsg::InputStats {} => to_json_binary(&InputStatsResponse {
+            inputs: InputStats::all(deps.storage)?,
+        }),
+        QueryMsg::TargetStats {} => to_json_binary(&TargetStatsResponse {
+            targets: TargetStats::all(deps.storage)?,
+        }),
+        QueryMsg::Failures {} => to_json_binary(&FailuresResponse {
+            failures: ActionFailures::all(deps.storage)?,
+        }),
+        QueryMsg::History { start_after, limit } => to_json_binary(&HistoryResponse {
+            runs: RunRecord::history(
+                deps.storage,
+                start_after,
+                limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize,
+            )?,
+        }),
+        QueryMsg::Rewards {} => to_json_binary(&RewardsResponse {
+            paid: ExecutorReward::paid(deps.storage)?,
+        }),
+        QueryMsg::PendingOwner {} => {
+            let pending = PendingOwner::load(deps.storage)?;
+            to_json_binary(&PendingOwnerResponse {
+                owner: pending.clone().map(|x| x.owner),
+                expiry: pending.and_then(|x| x.expiry),
+            })
+        }
     }
 }
 
+/// Sends the contract balance of `denom` to the target addresses, less the executor's reward,
+/// returning the amounts paid
 fn distribute_denom(
-    deps: Deps,
+    deps: Deps<KujiraQuery>,
     env: &Env,
     config: &Config,
+    executor: &Addr,
     sends: &mut Vec<CosmosMsg>,
-    denom: Denom,
-) -> StdResult<()> {
-    let balance = deps
-        .querier
-        .query_balance(env.contract.address.clone(), denom.to_string())?;
-
-    let total_weight = config.target_addresses.iter().fold(0, |a, e| e.1 + a);
-    if !balance.amount.is_zero() {
-        let mut remaining = balance.amount;
-        let mut targets = config.target_addresses.iter().peekable();
-
-        while let Some((addr, weight)) = targets.next() {
-            let amount = if targets.peek().is_none() {
-                remaining
-            } else {
-                let ratio = Decimal::from_ratio(*weight, total_weight);
-                balance.amount.mul_floor(ratio)
-            };
-
-            if amount.is_zero() {
-                continue;
-            }
-            remaining -= amount;
-            sends.push(denom.send(&addr, &amount))
+    denom: &Denom,
+) -> StdResult<Distribution> {
+    let total = balance(&deps.querier, env, denom.to_string())?;
+    let distribution = config.distribute(denom, total);
+    if !distribution.reward.is_zero() {
+        sends.push(denom.send(executor, &distribution.reward));
+    }
+    for (addr, amount) in distribution.sends.iter() {
+        if !amount.is_zero() {
+            sends.push(denom.send(addr, amount));
         }
-    };
-    Ok(())
+    }
+    Ok(distribution)
 }
 
-#[cfg(test)]
+/// Reports the Actions that the next Run would execute and the distributions it would make,
+/// at the current balances. A paused crank would reject the Run, so nothing is reported
+fn simulate_run(deps: Deps<KujiraQuery>, env: &Env) -> StdResult<SimulateRunResponse> {
+    let config = Config::load(deps.storage)?;
+    if config.paused {
+        return Ok(SimulateRunResponse {
+            paused: true,
+            actions: vec![],
+            distributions: vec![],
+        });
+    }
+    let querier = KujiraQuerier::new(&deps.querier);
+    let mut actions = vec![];
+    for action in Action::peek(deps.storage, config.batch_size as usize, &config.schedule)? {
+        let amount = deps
+            .querier
+            .query_balance(&env.contract.address, action.denom.to_string())?;
+        let allowance = ActionUsage::allowance(deps.storage, &action, &env.block)?;
+        let offer = action.offer(amount.amount, allowance);
+        // A pricing error skips the Action, as it would in a Run
+        let (funds, min_return) =
+            match action.execute(&querier, &env.contract.address, amount, allowance) {
+                Ok(Some((_, min_return))) => (Some(action.denom.coin(&offer)), min_return),
+                Ok(None) | Err(_) => (None, None),
+            };
+        actions.push(SimulatedActionResponse {
+            denom: action.denom,
+            contract: Addr::unchecked(action.contract),
+            funds,
+            min_return,
+        });
+    }
+
+    Ok(SimulateRunResponse {
+        paused: false,
+        actions,
+        distributions: distributions(deps, env, &config, None)?,
+    })
+}
+
+/// Reports how the current balance of each target denom, or only `denom`, would be distributed
+fn distributions(
+    deps: Deps<KujiraQuery>,
+    env: &Env,
+    config: &Config,
+    denom: Option<&Denom>,
+) -> StdResult<Vec<DistributionResponse>> {
+    if let Some(denom) = denom {
+        if !config.target_denoms.contains(denom) {
+            return Err(StdError::not_found(format!("Target denom {denom}")));
+        }
+    }
+    config
+        .target_denoms
+        .iter()
+        .filter(|x| denom.is_none() || denom == Some(*x))
+        .map(|denom| {
+            let total = balance(&deps.querier, env, denom.to_string())?;
+            let distribution = config.distribute(denom, total);
+            Ok(DistributionResponse {
+                denom: denom.clone(),
+                balance: total,
+                executor_reward: distribution.reward,
+                sends: distribution.sends,
+                remainder: distribution.remainder,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
 mod tests {
 
+    use std::marker::PhantomData;
+
     use super::*;
+    use crate::state::{ActionFailures, ExecutorReward, Hop, SwapMsg, VolumeCap, HISTORY_SIZE};
     use cosmwasm_std::{
         coin, coins, from_json,
-        testing::{mock_dependencies, mock_dependencies_with_balances, mock_env, mock_info},
-        BankMsg, ReplyOn, Uint128,
+        testing::{mock_env, mock_info, MockApi, MockQuerier, MockStorage},
+        Addr, Attribute, BankMsg, Coin, ContractResult, Decimal, Decimal256, OwnedDeps, ReplyOn,
+        Storage, SubMsgResponse, SubMsgResult, SystemError, SystemResult, Uint128, WasmMsg,
     };
-    use kujira::fee_address;
+    use cw_storage_plus::Map;
+    use cw_utils::Expiration;
+    use kujira::{fee_address, ExchangeRateResponse, OracleQuery};
+
+    fn mock_dependencies() -> OwnedDeps<MockStorage, MockApi, MockQuerier<KujiraQuery>, KujiraQuery>
+    {
+        mock_dependencies_with_balances(&[])
+    }
+
+    fn mock_dependencies_with_balances(
+        balances: &[(&str, &[Coin])],
+    ) -> OwnedDeps<MockStorage, MockApi, MockQuerier<KujiraQuery>, KujiraQuery> {
+        let querier = MockQuerier::new(balances).with_custom_handler(|query| match query {
+            KujiraQuery::Oracle(OracleQuery::ExchangeRate { denom }) => {
+                let rate = match denom.as_str() {
+                    "ATOM" => Decimal::from_ratio(10u128, 1u128),
+                    "KUJI" => Decimal::percent(50),
+                    _ => {
+                        return SystemResult::Err(SystemError::InvalidRequest {
+                            error: format!("No oracle price for {denom}"),
+                            request: Binary::default(),
+                        })
+                    }
+                };
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&ExchangeRateResponse { rate }).unwrap(),
+                ))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "kujira".to_string(),
+            }),
+        });
+        OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier,
+            custom_query_type: PhantomData,
+        }
+    }
+
+    fn guarded(msg: CosmosMsg, min_return: Coin, before: u128) -> CosmosMsg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "cosmos2contract".to_string(),
+            msg: to_json_binary(&ExecuteMsg::GuardedSwap {
+                msg,
+                min_return,
+                before: Uint128::from(before),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    }
 
     #[test]
     fn instantiation() {
         let mut deps = mock_dependencies();
-        let info = mock_info("owner", &vec![]);
+        let info = mock_info("owner", &[]);
         let msg = InstantiateMsg {
-            owner: Addr::unchecked("owner"),
+            owner: "owner".to_string(),
             target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
-            target_addresses: vec![(fee_address(), 1)],
-            executor: Addr::unchecked("executor"),
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
         };
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
         let config: ConfigResponse =
             from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
         assert_eq!(config.owner, Addr::unchecked("owner"));
         assert_eq!(
-            config.target_denoms,
-            vec![Denom::from("ukuji"), Denom::from("another")],
+            config.target_denoms,
+            vec![Denom::from("ukuji"), Denom::from("another")],
+        );
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, None);
+        let actions: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(actions.actions, vec![]);
+    }
+    #[test]
+    fn authorization() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("owner", &[]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ProposeOwner {
+                owner: "owner-new".to_string(),
+                expiry: None,
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::AcceptOwner {},
+        )
+        .unwrap_err();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner-new", &[]),
+            ExecuteMsg::AcceptOwner {},
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::ProposeOwner {
+                owner: "owner-new".to_string(),
+                expiry: None,
+            },
+        )
+        .unwrap_err();
+
+        let action = Action {
+            denom: Denom::from("uatom"),
+            contract: "fin".to_string(),
+            limit: Uint128::MAX,
+            msg: SwapMsg::Raw(Binary::default()),
+            min_return: None,
+            hops: vec![],
+            enabled: true,
+            weight: 1,
+            cooldown: None,
+            volume_cap: None,
+            max_fraction: None,
+            min_amount: None,
+            priority: 0,
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::SetAction(action.clone()),
+        )
+        .unwrap_err();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner-new", &[]),
+            ExecuteMsg::SetAction(action.clone()),
+        )
+        .unwrap();
+
+        let actions: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            actions.actions,
+            vec![ActionResponse {
+                denom: action.denom.clone(),
+                contract: Addr::unchecked(action.contract),
+                limit: action.limit,
+                msg: action.msg,
+                min_return: None,
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }]
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::UnsetAction {
+                denom: action.denom.clone(),
+                priority: None,
+            },
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner-new", &[]),
+            ExecuteMsg::UnsetAction {
+                denom: action.denom,
+                priority: None,
+            },
+        )
+        .unwrap();
+
+        let actions: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(actions.actions, vec![]);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner-new", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap_err();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn cranking() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[
+                // coin(1000u128, "token-a"),
+                coin(1000u128, "token-b"),
+                coin(1000u128, "token-c"),
+                coin(1000u128, "token-d"),
+                coin(1000u128, "token-e"),
+            ],
+        )]);
+        let info = mock_info("contract-0", &[]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        // Make sure that execution ends when there are no actions
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, None);
+
+        // Set some actions
+        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
+        set_action(deps.as_mut(), "token-b", "contract-b", Uint128::MAX);
+        set_action(
+            deps.as_mut(),
+            "token-c",
+            "contract-c",
+            Uint128::from(100u128),
+        );
+        set_action(deps.as_mut(), "token-d", "contract-d", Uint128::MAX);
+        set_action(deps.as_mut(), "token-e", "contract-e", Uint128::MAX);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        // Nothing done
+        assert_eq!(res.events.len(), 0);
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-a")));
+
+        // Iterator should start at the beginning again and execute token-a
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-b")));
+        assert_eq!(res.events[0].clone().ty, "revenue/run");
+        assert_eq!(res.events[0].clone().attributes[0].clone().key, "denom");
+        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-b");
+
+        // Run for c, d, e and then loop back to a
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-c");
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-d");
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-e");
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+
+        assert_eq!(res.events.len(), 0);
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-a")));
+    }
+
+    fn set_action(deps: DepsMut<KujiraQuery>, denom: &str, contract: &str, limit: Uint128) {
+        execute(
+            deps,
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from(denom),
+                contract: contract.to_string(),
+                limit,
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: None,
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn distribution() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "ukuji"), coin(2000u128, "another")],
+        )]);
+        let info = mock_info("contract-0", &[]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
+            target_addresses: vec![(fee_address().to_string(), 1), ("another".to_string(), 3)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // Dummy action to make sure it cranks the reply
+        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
+
+        // Make sure that execution ends when there are no actions
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+
+        assert!(res.messages.contains(&SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: "kujira17xpfvakm2amg962yls6f84z3kell8c5lp3pcxh".to_string(),
+                amount: coins(250, "ukuji"),
+            },),
+            gas_limit: None,
+            reply_on: ReplyOn::Never,
+        }));
+        assert!(res.messages.contains(&SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: "another".to_string(),
+                amount: coins(750, "ukuji"),
+            },),
+            gas_limit: None,
+            reply_on: ReplyOn::Never,
+        }));
+
+        assert!(res.messages.contains(&SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: "kujira17xpfvakm2amg962yls6f84z3kell8c5lp3pcxh".to_string(),
+                amount: coins(500, "another"),
+            },),
+            gas_limit: None,
+            reply_on: ReplyOn::Never,
+        }));
+
+        assert!(res.messages.contains(&SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: "another".to_string(),
+                amount: coins(1500, "another"),
+            },),
+            gas_limit: None,
+            reply_on: ReplyOn::Never,
+        }));
+    }
+
+    #[test]
+    fn min_return() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(100u128, "ukuji")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::from(500u128),
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: Some(MinReturn::Price {
+                    denom: Denom::from("ukuji"),
+                    price: Decimal::percent(50),
+                }),
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap();
+
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        // 500 token-a swapped at a floor of 0.5 requires 250 ukuji, checked within the swap
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let swap = CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "contract-a".to_string(),
+            msg: Binary::default(),
+            funds: coins(500, "token-a"),
+        });
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                guarded(swap.clone(), coin(250u128, "ukuji"), 100),
+                0
+            )]
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::GuardedSwap {
+                msg: swap.clone(),
+                min_return: coin(250u128, "ukuji"),
+                before: Uint128::from(100u128),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("cosmos2contract", &[]),
+            ExecuteMsg::GuardedSwap {
+                msg: swap,
+                min_return: coin(250u128, "ukuji"),
+                before: Uint128::from(100u128),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 2);
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(349u128, "ukuji")]);
+        let check = ExecuteMsg::CheckReturn {
+            min_return: coin(250u128, "ukuji"),
+            before: Uint128::from(100u128),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("cosmos2contract", &[]),
+            check.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientReturn { .. }));
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(350u128, "ukuji")]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("cosmos2contract", &[]),
+            check,
+        )
+        .unwrap();
+        reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(1000u128, "token-a"), coin(100u128, "ukuji")],
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(350u128, "ukuji")]);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: fee_address().to_string(),
+                amount: coins(350, "ukuji"),
+            })
+        );
+    }
+
+    #[test]
+    fn oracle_slippage() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::from(500u128),
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: Some(MinReturn::Oracle {
+                    denom: Denom::from("ukuji"),
+                    max_slippage: 100,
+                    offer: OracleAsset {
+                        symbol: "ATOM".to_string(),
+                        decimals: 6,
+                    },
+                    ask: OracleAsset {
+                        symbol: "KUJI".to_string(),
+                        decimals: 6,
+                    },
+                }),
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap();
+
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        // 500 token-a at 10 / 0.5 is worth 10000 ukuji, less 1% slippage
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(9899u128, "ukuji")]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("cosmos2contract", &[]),
+            ExecuteMsg::CheckReturn {
+                min_return: coin(9900u128, "ukuji"),
+                before: Uint128::zero(),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientReturn { expected, .. } if expected == coin(9900u128, "ukuji")
+        ));
+        reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(1000u128, "token-a")]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(9900u128, "ukuji")]);
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+    }
+
+    #[test]
+    fn hops() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(100u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::MAX,
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: Some(MinReturn::Amount {
+                    denom: Denom::from("ukuji"),
+                    amount: Uint128::from(500u128),
+                }),
+                hops: vec![Hop {
+                    denom: Denom::from("token-b"),
+                    contract: "contract-b".to_string(),
+                    msg: SwapMsg::Raw(Binary::default()),
+                }],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap();
+
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+
+        // Only the output of the first swap is offered to the second hop
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(400u128, "token-b")]);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+        assert_eq!(res.events[0].ty, "revenue/hop");
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                guarded(
+                    CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: "contract-b".to_string(),
+                        msg: Binary::default(),
+                        funds: coins(300, "token-b"),
+                    }),
+                    coin(500u128, "ukuji"),
+                    0
+                ),
+                0
+            )]
+        );
+
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(100u128, "token-b"), coin(600u128, "ukuji")],
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: fee_address().to_string(),
+                amount: coins(600, "ukuji"),
+            })
+        );
+    }
+
+    #[test]
+    fn batching() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-c")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: Some(3),
+        };
+        for batch_size in [0, MAX_BATCH_SIZE + 1] {
+            let msg = InstantiateMsg {
+                batch_size: Some(batch_size),
+                ..msg.clone()
+            };
+            let err =
+                instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidBatchSize { .. }));
+        }
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
+        set_action(deps.as_mut(), "token-b", "contract-b", Uint128::MAX);
+        set_action(deps.as_mut(), "token-c", "contract-c", Uint128::MAX);
+        set_action(deps.as_mut(), "token-d", "contract-d", Uint128::MAX);
+
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        // The requested batch is capped at the configured size
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run {
+                max_actions: Some(10),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-a");
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-c")));
+
+        // token-b has no balance, so the batch continues with token-c
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(1000u128, "token-c"), coin(100u128, "ukuji")],
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-c");
+        assert_eq!(res.messages.len(), 1);
+
+        // Distribution happens once the batch is complete
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(200u128, "ukuji")]);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(res.events.len(), 0);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: fee_address().to_string(),
+                amount: coins(200, "ukuji"),
+            })
+        );
+
+        // A Run must execute at least one Action
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run {
+                max_actions: Some(0),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBatchSize { .. }));
+
+        // A batch never repeats an Action
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run {
+                max_actions: Some(1),
+            },
+        )
+        .unwrap();
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-d")));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, Some(Denom::from("token-c")));
+    }
+
+    #[test]
+    fn run_denom() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-c")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
+        set_action(deps.as_mut(), "token-b", "contract-b", Uint128::MAX);
+        set_action(deps.as_mut(), "token-c", "contract-c", Uint128::MAX);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RunDenom(Denom::from("token-c")),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::RunDenom(Denom::from("token-x")),
+        )
+        .unwrap_err();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::RunDenom(Denom::from("token-c")),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-c");
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, None);
+
+        // The rotation is undisturbed
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-a");
+    }
+
+    #[test]
+    fn migration() {
+        let mut deps = mock_dependencies();
+        let upgrade = MigrateMsg::Upgrade {
+            owner: None,
+            executors: None,
+            target_denoms: None,
+            target_addresses: None,
+            batch_size: None,
+        };
+
+        // The stored addresses are validated
+        cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+        deps.storage.set(
+            b"config",
+            br#"{"owner":"owner","executor":"executor","target_denoms":["ukuji"],"target_addresses":[["FEE",1]]}"#,
+        );
+        let err = migrate(deps.as_mut(), mock_env(), upgrade.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidAddress { field, .. } if field == "target_addresses"
+        ));
+        deps.storage.set(
+            b"config",
+            br#"{"owner":"owner","executor":"executor","target_denoms":["ukuji"],"target_addresses":[["fee",1]]}"#,
+        );
+        let legacy = Map::<String, (Addr, Uint128, Binary)>::new("actions");
+        legacy
+            .save(
+                deps.as_mut().storage,
+                "token-z".to_string(),
+                &(
+                    Addr::unchecked("CONTRACT"),
+                    Uint128::from(100u128),
+                    Binary::from(b"{}"),
+                ),
+            )
+            .unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), upgrade.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidAddress { field, .. } if field == "actions[token-z].contract"
+        ));
+        legacy.remove(deps.as_mut().storage, "token-z".to_string());
+
+        // v0.1 stored Actions as (contract, limit, msg) tuples
+        deps.storage.set(
+            b"config",
+            br#"{"owner":"owner","executor":"executor","target_denoms":["ukuji"],"target_addresses":[["fee",1]]}"#,
+        );
+        Map::<String, (Addr, Uint128, Binary)>::new("actions")
+            .save(
+                deps.as_mut().storage,
+                "token-a".to_string(),
+                &(
+                    Addr::unchecked("contract-a"),
+                    Uint128::from(100u128),
+                    Binary::from(b"{}"),
+                ),
+            )
+            .unwrap();
+
+        let res = migrate(deps.as_mut(), mock_env(), upgrade.clone()).unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "0.1.0");
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.executors, vec![Addr::unchecked("executor")]);
+        assert_eq!(config.batch_size, 1);
+        let actions: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            actions.actions,
+            vec![ActionResponse {
+                denom: Denom::from("token-a"),
+                contract: Addr::unchecked("contract-a"),
+                limit: Uint128::from(100u128),
+                msg: SwapMsg::Raw(Binary::from(b"{}")),
+                min_return: None,
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }]
+        );
+        assert_eq!(
+            cw2::get_contract_version(deps.as_ref().storage)
+                .unwrap()
+                .version,
+            CONTRACT_VERSION
+        );
+
+        // Migrating within the same version only applies the overrides
+        migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg::Upgrade {
+                owner: Some("owner-new".to_string()),
+                executors: None,
+                target_denoms: None,
+                target_addresses: None,
+                batch_size: Some(5),
+            },
+        )
+        .unwrap();
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("owner-new"));
+        assert_eq!(config.executors, vec![Addr::unchecked("executor")]);
+        assert_eq!(config.batch_size, 5);
+        let err = migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg::Upgrade {
+                owner: None,
+                executors: None,
+                target_denoms: None,
+                target_addresses: None,
+                batch_size: Some(0),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBatchSize { .. }));
+
+        // Downgrades are refused
+        cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), upgrade.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidVersion { .. }));
+
+        // As are other contracts
+        cw2::set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), upgrade).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidContract { .. }));
+    }
+
+    #[test]
+    fn update_targets() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::UpdateTargetDenoms(TargetDenomsUpdate::Add(vec![Denom::from("usk")])),
+        )
+        .unwrap_err();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetDenoms(TargetDenomsUpdate::Add(vec![Denom::from("usk")])),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "revenue/config");
+        assert_eq!(res.events[0].attributes[0].key, "denom_added");
+        assert_eq!(res.events[0].attributes[0].value, "usk");
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetDenoms(TargetDenomsUpdate::Add(vec![Denom::from("usk")])),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateTarget { .. }));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetDenoms(TargetDenomsUpdate::Remove(vec![Denom::from("uatom")])),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::TargetNotFound { .. }));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetDenoms(TargetDenomsUpdate::Remove(vec![Denom::from("ukuji")])),
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetAddresses(TargetAddressesUpdate::Add(vec![(
+                "another".to_string(),
+                3,
+            )])),
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].key, "address_added");
+        assert_eq!(res.events[0].attributes[0].value, "another:3");
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetAddresses(TargetAddressesUpdate::Add(vec![(
+                "Invalid".to_string(),
+                1,
+            )])),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidAddress { .. }));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetAddresses(TargetAddressesUpdate::Replace(vec![])),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoTargetAddresses {}));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetAddresses(TargetAddressesUpdate::Replace(vec![
+                ("another".to_string(), 0),
+                ("fee".to_string(), 0),
+            ])),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ZeroTotalWeight {}));
+
+        // Changing a weight is reported as a removal and an addition
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateTargetAddresses(TargetAddressesUpdate::Replace(vec![(
+                "another".to_string(),
+                1,
+            )])),
+        )
+        .unwrap();
+        assert_eq!(
+            res.events[0].attributes,
+            vec![
+                Attribute::new("address_added", "another:1"),
+                Attribute::new("address_removed", format!("{}:1", fee_address())),
+                Attribute::new("address_removed", "another:3"),
+            ]
+        );
+
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.target_denoms, vec![Denom::from("usk")]);
+        assert_eq!(
+            config.target_addresses,
+            vec![(Addr::unchecked("another"), 1)]
+        );
+    }
+
+    #[test]
+    fn ownership_transfer() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::CancelOwner {},
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::ProposeOwner {
+                owner: "Invalid".to_string(),
+                expiry: None,
+            },
+        )
+        .unwrap_err();
+
+        let expiry = Expiration::AtHeight(mock_env().block.height + 10);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::ProposeOwner {
+                owner: "nominee".to_string(),
+                expiry: Some(expiry),
+            },
+        )
+        .unwrap();
+        let pending: PendingOwnerResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::PendingOwner {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            pending,
+            PendingOwnerResponse {
+                owner: Some(Addr::unchecked("nominee")),
+                expiry: Some(expiry),
+            }
+        );
+
+        // The proposal lapses at its expiry
+        let mut env = mock_env();
+        env.block.height += 10;
+        let err = execute(
+            deps.as_mut(),
+            env,
+            mock_info("nominee", &[]),
+            ExecuteMsg::AcceptOwner {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OwnershipProposalExpired {}));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::CancelOwner {},
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("nominee", &[]),
+            ExecuteMsg::AcceptOwner {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingOwner {}));
+        let pending: PendingOwnerResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::PendingOwner {}).unwrap())
+                .unwrap();
+        assert_eq!(pending.owner, None);
+
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("owner"));
+    }
+
+    #[test]
+    fn address_validation() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![("Fee".to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid Address: target_addresses Fee".to_string()
+        );
+
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::AddExecutor("Executor".to_string()),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidAddress { field, .. } if field == "executor"
+        ));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::MAX,
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: None,
+                hops: vec![Hop {
+                    denom: Denom::from("token-b"),
+                    contract: "Contract-B".to_string(),
+                    msg: SwapMsg::Raw(Binary::default()),
+                }],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidAddress { field, .. } if field == "hops[0].contract"
+        ));
+    }
+
+    #[test]
+    fn executors() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        let run = ExecuteMsg::Run { max_actions: None };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::AddExecutor("backup".to_string()),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::AddExecutor("backup".to_string()),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::AddExecutor("backup".to_string()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ExecutorExists { .. }));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("backup", &[]),
+            run.clone(),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            run.clone(),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            run.clone(),
+        )
+        .unwrap_err();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RemoveExecutor("executor".to_string()),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            run.clone(),
+        )
+        .unwrap_err();
+
+        // Anyone may crank once per interval when permissionless, which must be non-zero
+        for interval in [Duration::Height(0), Duration::Time(0)] {
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                ExecuteMsg::SetPermissionless(Some(interval)),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ContractError::InvalidInterval {}));
+        }
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetPermissionless(Some(Duration::Height(5))),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            run.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimited {}));
+
+        let mut env = mock_env();
+        env.block.height += 5;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            run.clone(),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            run.clone(),
+        )
+        .unwrap_err();
+        // Executors are not rate limited
+        execute(deps.as_mut(), env, mock_info("backup", &[]), run.clone()).unwrap();
+
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.executors, vec![Addr::unchecked("backup")]);
+        assert_eq!(config.permissionless, Some(Duration::Height(5)));
+    }
+
+    #[test]
+    fn executor_reward() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "ukuji"), coin(100000u128, "usk")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji"), Denom::from("usk")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let reward = ExecutorReward {
+            bps: 100,
+            caps: vec![(Denom::from("ukuji"), Uint128::from(5u128))],
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::SetExecutorReward(Some(reward.clone())),
+        )
+        .unwrap_err();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetExecutorReward(Some(ExecutorReward {
+                bps: 10001,
+                caps: vec![],
+            })),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidReward {}));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetExecutorReward(Some(reward)),
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let sends: Vec<CosmosMsg> = res.messages.into_iter().map(|x| x.msg).collect();
+        assert_eq!(
+            sends,
+            vec![
+                Denom::from("ukuji").send(&Addr::unchecked("executor"), &5u128),
+                Denom::from("ukuji").send(&fee_address(), &995u128),
+                Denom::from("usk").send(&Addr::unchecked("executor"), &1000u128),
+                Denom::from("usk").send(&fee_address(), &99000u128),
+            ]
+        );
+        assert_eq!(res.events[0].ty, "revenue/reward");
+
+        let rewards: RewardsResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Rewards {}).unwrap()).unwrap();
+        assert_eq!(
+            rewards.paid,
+            vec![coin(5u128, "ukuji"), coin(1000u128, "usk")]
+        );
+    }
+
+    #[test]
+    fn simulate_run() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[
+                coin(1000u128, "token-a"),
+                coin(500u128, "token-c"),
+                coin(1001u128, "ukuji"),
+            ],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![("target-a".to_string(), 1), ("target-b".to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: Some(2),
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(100u128),
+        );
+        set_action(
+            deps.as_mut(),
+            "token-b",
+            "contract-b",
+            Uint128::from(100u128),
+        );
+        set_action(
+            deps.as_mut(),
+            "token-c",
+            "contract-c",
+            Uint128::from(100u128),
+        );
+
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert!(!res.paused);
+        assert_eq!(
+            res.actions,
+            vec![
+                SimulatedActionResponse {
+                    denom: Denom::from("token-a"),
+                    contract: Addr::unchecked("contract-a"),
+                    funds: Some(coin(100u128, "token-a")),
+                    min_return: None,
+                },
+                SimulatedActionResponse {
+                    denom: Denom::from("token-b"),
+                    contract: Addr::unchecked("contract-b"),
+                    funds: None,
+                    min_return: None,
+                },
+            ]
+        );
+        assert_eq!(
+            res.distributions,
+            vec![DistributionResponse {
+                denom: Denom::from("ukuji"),
+                balance: Uint128::from(1001u128),
+                executor_reward: Uint128::zero(),
+                sends: vec![
+                    (Addr::unchecked("target-a"), Uint128::from(500u128)),
+                    (Addr::unchecked("target-b"), Uint128::from(501u128)),
+                ],
+                remainder: Some((Addr::unchecked("target-b"), Uint128::from(1u128))),
+            }]
+        );
+
+        // The simulation doesn't advance the rotation
+        let status: StatusResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(status.last, None);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].denom, Denom::from("token-c"));
+        assert_eq!(res.actions[1].denom, Denom::from("token-a"));
+    }
+
+    #[test]
+    fn distribution_query() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "ukuji"), coin(7u128, "usk")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji"), Denom::from("usk")],
+            target_addresses: vec![
+                ("target-a".to_string(), 1),
+                ("target-b".to_string(), 2),
+                ("target-c".to_string(), 0),
+            ],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let res: DistributionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Distribution { denom: None },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.distributions.len(), 2);
+        assert_eq!(
+            res.distributions[0].sends,
+            vec![
+                (Addr::unchecked("target-a"), Uint128::from(333u128)),
+                (Addr::unchecked("target-b"), Uint128::from(666u128)),
+                (Addr::unchecked("target-c"), Uint128::from(1u128)),
+            ]
+        );
+        assert_eq!(
+            res.distributions[0].remainder,
+            Some((Addr::unchecked("target-c"), Uint128::from(1u128)))
+        );
+
+        let res: DistributionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Distribution {
+                    denom: Some(Denom::from("usk")),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.distributions,
+            vec![DistributionResponse {
+                denom: Denom::from("usk"),
+                balance: Uint128::from(7u128),
+                executor_reward: Uint128::zero(),
+                sends: vec![
+                    (Addr::unchecked("target-a"), Uint128::from(2u128)),
+                    (Addr::unchecked("target-b"), Uint128::from(4u128)),
+                    (Addr::unchecked("target-c"), Uint128::from(1u128)),
+                ],
+                remainder: Some((Addr::unchecked("target-c"), Uint128::from(1u128))),
+            }]
+        );
+
+        // The preview matches the sends made by the crank
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 6);
+
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Distribution {
+                denom: Some(Denom::from("uatom")),
+            },
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn stats() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![("target-a".to_string(), 1), ("target-b".to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(400u128),
+        );
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        for _ in 0..2 {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("executor", &[]),
+                ExecuteMsg::Run { max_actions: None },
+            )
+            .unwrap();
+            deps.querier.update_balance(
+                "cosmos2contract",
+                vec![coin(600u128, "token-a"), coin(101u128, "ukuji")],
+            );
+            reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+        }
+
+        let res: InputStatsResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::InputStats {}).unwrap()).unwrap();
+        assert_eq!(
+            res.inputs,
+            vec![InputStats {
+                denom: Denom::from("token-a"),
+                sent: Uint128::from(800u128),
+                runs: 2,
+                last: LastRun {
+                    height: mock_env().block.height,
+                    time: mock_env().block.time,
+                },
+            }]
+        );
+
+        let res: TargetStatsResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::TargetStats {}).unwrap()).unwrap();
+        assert_eq!(
+            res.targets,
+            vec![TargetStats {
+                denom: Denom::from("ukuji"),
+                distributed: Uint128::from(202u128),
+                recipients: vec![
+                    (Addr::unchecked("target-a"), Uint128::from(100u128)),
+                    (Addr::unchecked("target-b"), Uint128::from(102u128)),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(400u128),
+        );
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(600u128, "token-a"), coin(50u128, "ukuji")],
+        );
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+
+        let res: HistoryResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::History {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.runs,
+            vec![(
+                0,
+                RunRecord {
+                    height: mock_env().block.height,
+                    time: mock_env().block.time,
+                    executor: Addr::unchecked("executor"),
+                    swaps: vec![SwapRecord {
+                        denom: Denom::from("token-a"),
+                        contract: Addr::unchecked("contract-a"),
+                        amount: Uint128::from(400u128),
+                        output: vec![coin(50u128, "ukuji")],
+                        error: None,
+                    }],
+                    distributions: vec![coin(50u128, "ukuji")],
+                }
+            )]
+        );
+
+        // The oldest Runs are evicted once the history is full
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(50u128, "ukuji")]);
+        for _ in 0..HISTORY_SIZE {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("executor", &[]),
+                ExecuteMsg::Run { max_actions: None },
+            )
+            .unwrap();
+        }
+        let res: HistoryResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::History {
+                    start_after: None,
+                    limit: Some(100),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.runs.len(), 30);
+        assert_eq!(res.runs[0].0, 1);
+        assert!(res.runs[0].1.swaps.is_empty());
+
+        let res: HistoryResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::History {
+                    start_after: Some(95),
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let ids: Vec<u64> = res.runs.iter().map(|x| x.0).collect();
+        assert_eq!(ids, vec![96, 97, 98, 99, 100]);
+    }
+
+    #[test]
+    fn failures() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: Some(2),
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(100u128),
+        );
+        set_action(
+            deps.as_mut(),
+            "token-b",
+            "contract-b",
+            Uint128::from(100u128),
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::SetMaxFailures(Some(2)),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetMaxFailures(Some(2)),
+        )
+        .unwrap();
+
+        let failed = Reply {
+            id: 0,
+            result: SubMsgResult::Err("insufficient liquidity".to_string()),
+        };
+        let succeeded = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+
+        for consecutive in 1..=2 {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("executor", &[]),
+                ExecuteMsg::Run { max_actions: None },
+            )
+            .unwrap();
+
+            // The failure is reported and the batch continues with token-b
+            let res = reply(deps.as_mut(), mock_env(), failed.clone()).unwrap();
+            assert_eq!(res.events[0].ty, "revenue/failure");
+            assert_eq!(
+                res.events[0].attributes[..3],
+                [
+                    Attribute::new("denom", "token-a"),
+                    Attribute::new("error", "insufficient liquidity"),
+                    Attribute::new("consecutive", consecutive.to_string()),
+                ]
+            );
+            assert_eq!(res.events[1].attributes[0].value, "token-b");
+            reply(deps.as_mut(), mock_env(), succeeded.clone()).unwrap();
+        }
+
+        let res: FailuresResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Failures {}).unwrap()).unwrap();
+        assert_eq!(
+            res.failures,
+            vec![ActionFailures {
+                denom: Denom::from("token-a"),
+                priority: 0,
+                consecutive: 2,
+                total: 2,
+            }]
+        );
+
+        // The Action is disabled after the second consecutive failure, and skipped by the crank
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(!res.actions[0].enabled);
+        assert!(res.actions[1].enabled);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-b");
+        let res = reply(deps.as_mut(), mock_env(), succeeded).unwrap();
+        assert!(res.events.is_empty());
+
+        // The failure is kept in the history
+        let res: HistoryResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::History {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.runs[0].1.swaps[0].error,
+            Some("insufficient liquidity".to_string())
+        );
+
+        // Setting the Action again re-enables it and clears its consecutive failures
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(100u128),
+        );
+        let res: FailuresResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Failures {}).unwrap()).unwrap();
+        assert_eq!(res.failures[0].consecutive, 0);
+        assert_eq!(res.failures[0].total, 2);
+    }
+
+    #[test]
+    fn pause() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(
+            deps.as_mut(),
+            "token-a",
+            "contract-a",
+            Uint128::from(100u128),
+        );
+        set_action(
+            deps.as_mut(),
+            "token-b",
+            "contract-b",
+            Uint128::from(100u128),
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::PauseAction {
+                denom: Denom::from("token-a"),
+                priority: None,
+            },
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::PauseAction {
+                denom: Denom::from("token-a"),
+                priority: None,
+            },
+        )
+        .unwrap();
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(!res.actions[0].enabled);
+
+        // The paused Action is kept, but skipped by the crank
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-b");
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::RunDenom(Denom::from("token-a")),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ActionPaused { .. }));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::ResumeAction {
+                denom: Denom::from("token-a"),
+                priority: None,
+            },
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].attributes[0].value, "token-a");
+
+        // Pausing the whole crank
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap();
+        let config: ConfigResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert!(config.paused);
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert!(res.paused);
+        assert!(res.actions.is_empty());
+        assert!(res.distributions.is_empty());
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::RunDenom(Denom::from("token-b")),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::Resume {},
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn emergency() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(500u128, "ukuji")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            ExecuteMsg::SetGuardian(Some("guardian".to_string())),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetGuardian(Some("guardian".to_string())),
+        )
+        .unwrap();
+
+        // The sweep is only available while paused
+        let sweep = ExecuteMsg::Sweep {
+            recipient: "recovery".to_string(),
+            denoms: vec![Denom::from("token-a"), Denom::from("token-b")],
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            sweep.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NotPaused {}));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+
+        // Only the owner may sweep or resume
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            sweep.clone(),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            ExecuteMsg::Resume {},
+        )
+        .unwrap_err();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), sweep).unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|x| x.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![Denom::from("token-a").send(&Addr::unchecked("recovery"), &1000u128)]
+        );
+        assert_eq!(
+            res.events[0].attributes,
+            vec![
+                Attribute::new("recipient", "recovery"),
+                Attribute::new("amount", "1000token-a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn actions_query() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        set_action(deps.as_mut(), "token-a", "fin-a", Uint128::MAX);
+        set_action(deps.as_mut(), "token-b", "fin-b", Uint128::MAX);
+        set_action(deps.as_mut(), "token-c", "fin-a", Uint128::MAX);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-d"),
+                contract: "fin-d".to_string(),
+                limit: Uint128::MAX,
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: None,
+                hops: vec![Hop {
+                    denom: Denom::from("token-a"),
+                    contract: "fin-a".to_string(),
+                    msg: SwapMsg::Raw(Binary::default()),
+                }],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
+        )
+        .unwrap();
+
+        let denoms = |res: ActionsResponse| -> Vec<String> {
+            res.actions
+                .into_iter()
+                .map(|x| x.denom.to_string())
+                .collect()
+        };
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: Some(2),
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(denoms(res), vec!["token-a", "token-b"]);
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: Some((Denom::from("token-b"), 0)),
+                    limit: Some(2),
+                    contract: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(denoms(res), vec!["token-c", "token-d"]);
+
+        // Actions routed through a contract, by their swap or a hop
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: None,
+                    contract: Some("fin-a".to_string()),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(denoms(res), vec!["token-a", "token-c", "token-d"]);
+
+        // The limit bounds the Actions scanned, not only those returned
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: None,
+                    limit: Some(2),
+                    contract: Some("fin-a".to_string()),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.next_start_after, Some((Denom::from("token-b"), 0)));
+        assert_eq!(denoms(res), vec!["token-a"]);
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Actions {
+                    start_after: Some((Denom::from("token-d"), 0)),
+                    limit: Some(2),
+                    contract: Some("fin-a".to_string()),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.next_start_after, None);
+        assert!(res.actions.is_empty());
+
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Action {
+                    denom: Denom::from("token-b"),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.actions[0].contract, Addr::unchecked("fin-b"));
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Action {
+                denom: Denom::from("token-e"),
+            },
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn fallback() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: Some(2),
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        for (priority, contract) in [(0, "fin-a"), (1, "bow-a"), (2, "fin-a-2")] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                ExecuteMsg::SetAction(Action {
+                    denom: Denom::from("token-a"),
+                    contract: contract.to_string(),
+                    limit: Uint128::from(100u128),
+                    msg: SwapMsg::Raw(Binary::default()),
+                    min_return: None,
+                    hops: vec![],
+                    enabled: true,
+                    weight: 1,
+                    cooldown: None,
+                    volume_cap: None,
+                    max_fraction: None,
+                    min_amount: None,
+                    priority,
+                }),
+            )
+            .unwrap();
+        }
+        set_action(deps.as_mut(), "token-b", "fin-b", Uint128::from(100u128));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::PauseAction {
+                denom: Denom::from("token-a"),
+                priority: Some(1),
+            },
+        )
+        .unwrap();
+
+        let failed = Reply {
+            id: 0,
+            result: SubMsgResult::Err("pool drained".to_string()),
+        };
+        let succeeded = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        let contract = |res: &Response| match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => contract_addr.clone(),
+            _ => panic!("unexpected msg"),
+        };
+
+        // Only the first enabled route of each denom is queued
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(contract(&res), "fin-a");
+
+        // When it fails, the next enabled route is tried before the rest of the batch
+        let res = reply(deps.as_mut(), mock_env(), failed).unwrap();
+        assert_eq!(res.events[0].ty, "revenue/failure");
+        assert_eq!(
+            res.events[0].attributes.last(),
+            Some(&Attribute::new("fallback", "2"))
+        );
+        assert_eq!(contract(&res), "fin-a-2");
+        let res = reply(deps.as_mut(), mock_env(), succeeded).unwrap();
+        assert_eq!(contract(&res), "fin-b");
+
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Action {
+                    denom: Denom::from("token-a"),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let priorities: Vec<u8> = res.actions.iter().map(|x| x.priority).collect();
+        assert_eq!(priorities, vec![0, 1, 2]);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UnsetAction {
+                denom: Denom::from("token-a"),
+                priority: Some(0),
+            },
+        )
+        .unwrap();
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].contract, Addr::unchecked("fin-a-2"));
+    }
+
+    #[test]
+    fn template() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "uatom")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        // The belief price is only available with an Oracle min_return
+        let mut action = Action {
+            denom: Denom::from("uatom"),
+            contract: "fin".to_string(),
+            limit: Uint128::from(400u128),
+            msg: SwapMsg::Template(
+                r#"{"swap":{"offer_amount":"{{offer_amount}}","offer_denom":"{{offer_denom}}","to":"{{contract}}","belief_price":"{{belief_price}}"}}"#.to_string(),
+            ),
+            min_return: None,
+            hops: vec![],
+            enabled: true,
+            weight: 1,
+            cooldown: None,
+            volume_cap: None,
+            max_fraction: None,
+            min_amount: None,
+            priority: 0,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(action.clone()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTemplate { .. }));
+
+        action.min_return = Some(MinReturn::Oracle {
+            denom: Denom::from("ukuji"),
+            max_slippage: 100,
+            offer: OracleAsset {
+                symbol: "ATOM".to_string(),
+                decimals: 6,
+            },
+            ask: OracleAsset {
+                symbol: "KUJI".to_string(),
+                decimals: 6,
+            },
+        });
+
+        // Nor to hops
+        let mut invalid = action.clone();
+        invalid.hops = vec![Hop {
+            denom: Denom::from("uusk"),
+            contract: "fin-2".to_string(),
+            msg: action.msg.clone(),
+        }];
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(invalid),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTemplate { .. }));
+
+        // The rendered template must be JSON
+        let mut invalid = action.clone();
+        invalid.msg = SwapMsg::Template(r#"{"swap":{"offer_amount":{{offer_amount}}}"#.to_string());
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(invalid),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTemplate { .. }));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(action),
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            guarded(
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "fin".to_string(),
+                    msg: Binary::from(
+                        br#"{"swap":{"offer_amount":"400","offer_denom":"uatom","to":"cosmos2contract","belief_price":"20"}}"#,
+                    ),
+                    funds: coins(400, "uatom"),
+                }),
+                coin(7920u128, "ukuji"),
+                0
+            )
         );
-        let status: StatusResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
-        assert_eq!(status.last, None);
-        let actions: ActionsResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Actions {}).unwrap()).unwrap();
-        assert_eq!(actions.actions, vec![]);
     }
+
     #[test]
-    fn authorization() {
-        let mut deps = mock_dependencies();
-        let info = mock_info("owner", &vec![]);
+    fn fin_action() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "uatom")])]);
         let msg = InstantiateMsg {
-            owner: Addr::unchecked("owner"),
-            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
-            target_addresses: vec![(fee_address(), 1)],
-            executor: Addr::unchecked("executor"),
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        let swap = SwapMsg::Fin {
+            belief_price: Some(Decimal256::percent(50)),
+            max_spread: Some(Decimal256::percent(1)),
         };
-        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-
         execute(
             deps.as_mut(),
             mock_env(),
-            info.clone(),
-            ExecuteMsg::SetOwner(Addr::unchecked("owner-new")),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("uatom"),
+                contract: "fin".to_string(),
+                limit: Uint128::MAX,
+                msg: swap.clone(),
+                min_return: None,
+                hops: vec![Hop {
+                    denom: Denom::from("uusk"),
+                    contract: "fin-2".to_string(),
+                    msg: SwapMsg::Fin {
+                        belief_price: None,
+                        max_spread: None,
+                    },
+                }],
+                enabled: true,
+                weight: 1,
+                cooldown: None,
+                volume_cap: None,
+                max_fraction: None,
+                min_amount: None,
+                priority: 0,
+            }),
         )
         .unwrap();
 
-        execute(
+        // The route is reported in its typed form
+        let res: ActionsResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Action {
+                    denom: Denom::from("uatom"),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.actions[0].msg, swap);
+
+        let res = execute(
             deps.as_mut(),
             mock_env(),
-            info.clone(),
-            ExecuteMsg::SetOwner(Addr::unchecked("owner-new")),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
         )
-        .unwrap_err();
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "fin".to_string(),
+                msg: Binary::from(
+                    br#"{"swap":{"offer_asset":null,"belief_price":"0.5","max_spread":"0.01","to":null}}"#,
+                ),
+                funds: coins(1000, "uatom"),
+            })
+        );
+    }
 
-        let action = Action {
-            denom: Denom::from("uatom"),
-            contract: Addr::unchecked("fin"),
+    #[test]
+    fn offer_limits() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        let mut action = Action {
+            denom: Denom::from("token-a"),
+            contract: "contract-a".to_string(),
             limit: Uint128::MAX,
-            msg: Binary::default(),
+            max_fraction: Some(Decimal::percent(101)),
+            min_amount: Some(Uint128::from(100u128)),
+            msg: SwapMsg::Raw(Binary::default()),
+            min_return: None,
+            hops: vec![],
+            enabled: true,
+            weight: 1,
+            cooldown: None,
+            volume_cap: None,
+            priority: 0,
         };
-
-        execute(
+        let err = execute(
             deps.as_mut(),
             mock_env(),
-            info.clone(),
+            mock_info("owner", &[]),
             ExecuteMsg::SetAction(action.clone()),
         )
         .unwrap_err();
-
+        assert!(matches!(err, ContractError::InvalidFraction {}));
+        action.max_fraction = Some(Decimal::percent(25));
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("owner-new", &vec![]),
-            ExecuteMsg::SetAction(action.clone()),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(action),
         )
         .unwrap();
 
-        let actions: ActionsResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Actions {}).unwrap()).unwrap();
+        // Only a quarter of the balance is offered
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
         assert_eq!(
-            actions.actions,
-            vec![ActionResponse {
-                denom: action.denom.clone(),
-                contract: action.contract,
-                limit: action.limit,
-                msg: action.msg
-            }]
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "contract-a".to_string(),
+                msg: Binary::default(),
+                funds: coins(250, "token-a"),
+            })
         );
 
-        execute(
+        // A quarter of the remaining balance is below the minimum, so the Action is skipped
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(300u128, "token-a"), coin(50u128, "ukuji")],
+        );
+        let res = execute(
             deps.as_mut(),
             mock_env(),
-            info,
-            ExecuteMsg::UnsetAction(action.denom.clone()),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
         )
-        .unwrap_err();
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            Denom::from("ukuji").send(&fee_address(), &50u128)
+        );
+    }
+
+    #[test]
+    fn volume_caps() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("owner-new", &vec![]),
-            ExecuteMsg::UnsetAction(action.denom),
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::from(300u128),
+                max_fraction: None,
+                min_amount: None,
+                cooldown: Some(Duration::Height(10)),
+                volume_cap: Some(VolumeCap {
+                    amount: Uint128::from(300u128),
+                    window: Duration::Height(100),
+                }),
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: None,
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                priority: 0,
+            }),
         )
         .unwrap();
+        let reply_msg = Reply {
+            id: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        let run = |deps: DepsMut<KujiraQuery>, height: u64| {
+            let mut env = mock_env();
+            env.block.height = height;
+            execute(
+                deps,
+                env,
+                mock_info("executor", &[]),
+                ExecuteMsg::Run { max_actions: None },
+            )
+            .unwrap()
+        };
+        let reply_at = |deps: DepsMut<KujiraQuery>, height: u64| {
+            let mut env = mock_env();
+            env.block.height = height;
+            reply(deps, env, reply_msg.clone()).unwrap()
+        };
+        let swap = |amount: u128| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "contract-a".to_string(),
+                msg: Binary::default(),
+                funds: coins(amount, "token-a"),
+            })
+        };
 
-        let actions: ActionsResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Actions {}).unwrap()).unwrap();
-        assert_eq!(actions.actions, vec![]);
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(100u128, "token-a")]);
+        let res = run(deps.as_mut(), 1000);
+        assert_eq!(res.messages[0].msg, swap(100));
+        reply_at(deps.as_mut(), 1000);
 
-        execute(
-            deps.as_mut(),
-            mock_env(),
-            mock_info("owner-new", &vec![]),
-            ExecuteMsg::Run {},
-        )
-        .unwrap_err();
+        // Skipped during the cooldown
+        deps.querier
+            .update_balance("cosmos2contract", vec![coin(1000u128, "token-a")]);
+        let res = run(deps.as_mut(), 1005);
+        assert_eq!(res.messages.len(), 0);
+
+        // Limited to the remainder of the volume cap within the window
+        let res = run(deps.as_mut(), 1090);
+        assert_eq!(res.messages[0].msg, swap(200));
+        reply_at(deps.as_mut(), 1090);
+
+        // The window rolls, so only the volume swapped more than a window ago is released
+        let res = run(deps.as_mut(), 1100);
+        assert_eq!(res.messages[0].msg, swap(100));
+        reply_at(deps.as_mut(), 1100);
+        let res = run(deps.as_mut(), 1150);
+        assert_eq!(res.messages.len(), 0);
+        let res = run(deps.as_mut(), 1190);
+        assert_eq!(res.messages[0].msg, swap(200));
 
+        // A cooldown too long to represent never elapses, rather than overflowing
+        reply_at(deps.as_mut(), 1190);
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(Action {
+                denom: Denom::from("token-a"),
+                contract: "contract-a".to_string(),
+                limit: Uint128::from(300u128),
+                max_fraction: None,
+                min_amount: None,
+                cooldown: Some(Duration::Time(u64::MAX)),
+                volume_cap: Some(VolumeCap {
+                    amount: Uint128::from(300u128),
+                    window: Duration::Height(u64::MAX),
+                }),
+                msg: SwapMsg::Raw(Binary::default()),
+                min_return: None,
+                hops: vec![],
+                enabled: true,
+                weight: 1,
+                priority: 0,
+            }),
         )
         .unwrap();
+        let res = run(deps.as_mut(), u64::MAX);
+        assert_eq!(res.messages.len(), 0);
     }
 
     #[test]
-    fn cranking() {
+    fn weighted_schedule() {
         let mut deps = mock_dependencies_with_balances(&[(
             "cosmos2contract",
             &[
-                // coin(1000u128, "token-a"),
+                coin(1000u128, "token-a"),
                 coin(1000u128, "token-b"),
                 coin(1000u128, "token-c"),
-                coin(1000u128, "token-d"),
-                coin(1000u128, "token-e"),
             ],
         )]);
-        let info = mock_info("contract-0", &vec![]);
         let msg = InstantiateMsg {
-            owner: Addr::unchecked("owner"),
-            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
-            target_addresses: vec![(fee_address(), 1)],
-            executor: Addr::unchecked("executor"),
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
         };
-        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-
-        // Make sure that execution ends when there are no actions
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        for (denom, weight) in [("token-a", 3), ("token-b", 1), ("token-c", 1)] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                ExecuteMsg::SetAction(Action {
+                    denom: Denom::from(denom),
+                    contract: format!("contract-{}", &denom[6..]),
+                    limit: Uint128::from(1u128),
+                    max_fraction: None,
+                    min_amount: None,
+                    cooldown: None,
+                    volume_cap: None,
+                    weight,
+                    msg: SwapMsg::Raw(Binary::default()),
+                    min_return: None,
+                    hops: vec![],
+                    enabled: true,
+                    priority: 0,
+                }),
+            )
+            .unwrap();
+        }
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::SetSchedule(Schedule::Weighted),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("owner", &[]),
+            ExecuteMsg::SetSchedule(Schedule::Weighted),
         )
         .unwrap();
-        let status: StatusResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
-        assert_eq!(status.last, None);
 
-        // Set some actions
-        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
-        set_action(deps.as_mut(), "token-b", "contract-b", Uint128::MAX);
-        set_action(
-            deps.as_mut(),
-            "token-c",
-            "contract-c",
-            Uint128::from(100u128),
+        let mut scheduled = vec![];
+        for _ in 0..10 {
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("executor", &[]),
+                ExecuteMsg::Run { max_actions: None },
+            )
+            .unwrap();
+            match &res.messages[0].msg {
+                CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+                    scheduled.push(contract_addr.clone())
+                }
+                msg => panic!("unexpected msg {msg:?}"),
+            }
+        }
+        assert_eq!(
+            scheduled,
+            vec![
+                "contract-a",
+                "contract-b",
+                "contract-c",
+                "contract-a",
+                "contract-a",
+                "contract-a",
+                "contract-b",
+                "contract-c",
+                "contract-a",
+                "contract-a",
+            ]
         );
-        set_action(deps.as_mut(), "token-d", "contract-d", Uint128::MAX);
-        set_action(deps.as_mut(), "token-e", "contract-e", Uint128::MAX);
 
-        let res = execute(
+        // A denom without Actions leaves the schedule
+        execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("owner", &[]),
+            ExecuteMsg::UnsetAction {
+                denom: Denom::from("token-a"),
+                priority: None,
+            },
         )
         .unwrap();
-        // Nothing done
-        assert_eq!(res.events.len(), 0);
-        let status: StatusResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
-        assert_eq!(status.last, Some(Denom::from("token-a")));
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].contract, Addr::unchecked("contract-b"));
 
-        // Iterator should start at the beginning again and execute token-a
-        let res = execute(
+        // Nor does a paused denom, which is rescheduled at the current pass when resumed
+        let pause = ExecuteMsg::PauseAction {
+            denom: Denom::from("token-b"),
+            priority: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), pause).unwrap();
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].contract, Addr::unchecked("contract-c"));
+        let resume = ExecuteMsg::ResumeAction {
+            denom: Denom::from("token-b"),
+            priority: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), resume).unwrap();
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].contract, Addr::unchecked("contract-b"));
+    }
+
+    #[test]
+    fn shortfall() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        for denom in ["token-a", "token-b"] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                ExecuteMsg::SetAction(Action {
+                    denom: Denom::from(denom),
+                    contract: "contract-a".to_string(),
+                    limit: Uint128::from(500u128),
+                    max_fraction: None,
+                    min_amount: None,
+                    cooldown: None,
+                    volume_cap: None,
+                    weight: 1,
+                    msg: SwapMsg::Raw(Binary::default()),
+                    min_return: Some(MinReturn::Amount {
+                        denom: Denom::from("ukuji"),
+                        amount: Uint128::from(250u128),
+                    }),
+                    hops: vec![],
+                    enabled: true,
+                    priority: 0,
+                }),
+            )
+            .unwrap();
+        }
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        // The guarded swap reverts on a shortfall, which is handled as a failed swap
+        let res = reply(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            Reply {
+                id: 0,
+                result: SubMsgResult::Err(
+                    "Insufficient Return: expected 250ukuji, received 249ukuji".to_string(),
+                ),
+            },
         )
         .unwrap();
+        assert_eq!(res.events[0].ty, "revenue/failure");
+
         let status: StatusResponse =
             from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
-        assert_eq!(status.last, Some(Denom::from("token-b")));
-        assert_eq!(res.events[0].clone().ty, "revenue/run");
-        assert_eq!(res.events[0].clone().attributes[0].clone().key, "denom");
-        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-b");
+        assert_eq!(status.last, Some(Denom::from("token-a")));
+        let res: FailuresResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Failures {}).unwrap()).unwrap();
+        assert_eq!(
+            res.failures,
+            vec![ActionFailures {
+                denom: Denom::from("token-a"),
+                priority: 0,
+                consecutive: 1,
+                total: 1,
+            }]
+        );
 
-        // Run for c, d, e and then loop back to a
+        // The rotation moves on to the next denom
         let res = execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
         )
         .unwrap();
-        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-c");
+        assert_eq!(res.events[0].attributes[0].value, "token-b");
+    }
 
-        let res = execute(
+    #[test]
+    fn oracle_failure() {
+        let mut deps = mock_dependencies_with_balances(&[(
+            "cosmos2contract",
+            &[coin(1000u128, "token-a"), coin(1000u128, "token-b")],
+        )]);
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: Some(2),
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        let mut action = Action {
+            denom: Denom::from("token-a"),
+            contract: "contract-a".to_string(),
+            limit: Uint128::from(500u128),
+            max_fraction: None,
+            min_amount: None,
+            cooldown: None,
+            volume_cap: None,
+            weight: 1,
+            msg: SwapMsg::Raw(Binary::default()),
+            min_return: Some(MinReturn::Oracle {
+                denom: Denom::from("ukuji"),
+                max_slippage: 100,
+                offer: OracleAsset {
+                    symbol: "MISSING".to_string(),
+                    decimals: 6,
+                },
+                ask: OracleAsset {
+                    symbol: "KUJI".to_string(),
+                    decimals: 6,
+                },
+            }),
+            hops: vec![],
+            enabled: true,
+            priority: 0,
+        };
+        // Both oracle symbols must be priced when the Action is set
+        execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(action.clone()),
         )
-        .unwrap();
-        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-d");
+        .unwrap_err();
 
-        let res = execute(
+        // A symbol that loses its price afterwards only skips its Action
+        Action::set(deps.as_mut().storage, action.clone()).unwrap();
+        action.denom = Denom::from("token-b");
+        action.min_return = None;
+        execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("owner", &[]),
+            ExecuteMsg::SetAction(action),
         )
         .unwrap();
-        assert_eq!(res.events[0].clone().attributes[0].clone().value, "token-e");
 
         let res = execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
         )
         .unwrap();
+        assert_eq!(res.events[0].ty, "revenue/failure");
+        assert_eq!(res.events[0].attributes[0].value, "token-a");
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "contract-a".to_string(),
+                    msg: Binary::default(),
+                    funds: coins(500, "token-b"),
+                }),
+                0
+            )]
+        );
+        let res: FailuresResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Failures {}).unwrap()).unwrap();
+        assert_eq!(res.failures.len(), 1);
+        assert_eq!(res.failures[0].denom, Denom::from("token-a"));
 
-        assert_eq!(res.events.len(), 0);
-        let status: StatusResponse =
-            from_json(query(deps.as_ref(), mock_env(), QueryMsg::Status {}).unwrap()).unwrap();
-        assert_eq!(status.last, Some(Denom::from("token-a")));
-    }
+        // The simulation skips it too
+        let res: SimulateRunResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::SimulateRun {}).unwrap()).unwrap();
+        assert_eq!(res.actions[0].funds, None);
 
-    fn set_action(deps: DepsMut, denom: &str, contract: &str, limit: Uint128) {
-        execute(
-            deps,
+        // Nothing was sent for the skipped Action
+        reply(
+            deps.as_mut(),
             mock_env(),
-            mock_info("owner", &vec![]),
-            ExecuteMsg::SetAction(Action {
-                denom: Denom::from(denom),
-                contract: Addr::unchecked(contract),
-                limit: limit,
-                msg: Binary::default(),
-            }),
+            Reply {
+                id: 0,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        let res: HistoryResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::History {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
         )
         .unwrap();
+        let swaps = &res.runs[0].1.swaps;
+        assert_eq!(swaps[0].denom, Denom::from("token-a"));
+        assert_eq!(swaps[0].amount, Uint128::zero());
+        assert!(swaps[0].error.is_some());
+        assert_eq!(swaps[1].amount, Uint128::from(500u128));
     }
 
     #[test]
-    fn distribution() {
-        let mut deps = mock_dependencies_with_balances(&[(
-            "cosmos2contract",
-            &[coin(1000u128, "ukuji"), coin(2000u128, "another")],
-        )]);
-        let info = mock_info("contract-0", &vec![]);
+    fn hop_failure() {
+        let mut deps =
+            mock_dependencies_with_balances(&[("cosmos2contract", &[coin(1000u128, "token-a")])]);
         let msg = InstantiateMsg {
-            owner: Addr::unchecked("owner"),
-            target_denoms: vec![Denom::from("ukuji"), Denom::from("another")],
-            target_addresses: vec![(fee_address(), 1), (Addr::unchecked("another"), 3)],
-            executor: Addr::unchecked("executor"),
+            owner: "owner".to_string(),
+            target_denoms: vec![Denom::from("ukuji")],
+            target_addresses: vec![(fee_address().to_string(), 1)],
+            executors: vec!["executor".to_string()],
+            batch_size: None,
         };
-        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-        // Dummy action to make sure it cranks the reply
-        set_action(deps.as_mut(), "token-a", "contract-a", Uint128::MAX);
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        for (priority, contract) in [(0, "fin-a"), (1, "fin-a-2")] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                ExecuteMsg::SetAction(Action {
+                    denom: Denom::from("token-a"),
+                    contract: contract.to_string(),
+                    limit: Uint128::from(100u128),
+                    max_fraction: None,
+                    min_amount: None,
+                    cooldown: None,
+                    volume_cap: None,
+                    weight: 1,
+                    msg: SwapMsg::Raw(Binary::default()),
+                    min_return: None,
+                    hops: vec![Hop {
+                        denom: Denom::from("token-b"),
+                        contract: "fin-b".to_string(),
+                        msg: SwapMsg::Raw(Binary::default()),
+                    }],
+                    enabled: true,
+                    priority,
+                }),
+            )
+            .unwrap();
+        }
 
-        // Make sure that execution ends when there are no actions
-        let res = execute(
+        execute(
             deps.as_mut(),
             mock_env(),
-            mock_info("executor", &vec![]),
-            ExecuteMsg::Run {},
+            mock_info("executor", &[]),
+            ExecuteMsg::Run { max_actions: None },
+        )
+        .unwrap();
+        deps.querier.update_balance(
+            "cosmos2contract",
+            vec![coin(900u128, "token-a"), coin(50u128, "token-b")],
+        );
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: 0,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
         )
         .unwrap();
 
-        assert!(res.messages.contains(&SubMsg {
-            id: 0,
-            msg: CosmosMsg::Bank(BankMsg::Send {
-                to_address: "kujira17xpfvakm2amg962yls6f84z3kell8c5lp3pcxh".to_string(),
-                amount: coins(250, "ukuji"),
-            },),
-            gas_limit: None,
-            reply_on: ReplyOn::Never,
-        }));
-        assert!(res.messages.contains(&SubMsg {
-            id: 0,
-            msg: CosmosMsg::Bank(BankMsg::Send {
-                to_address: "another".to_string(),
-                amount: coins(750, "ukuji"),
-            },),
-            gas_limit: None,
-            reply_on: ReplyOn::Never,
-        }));
-
-        assert!(res.messages.contains(&SubMsg {
-            id: 0,
-            msg: CosmosMsg::Bank(BankMsg::Send {
-                to_address: "kujira17xpfvakm2amg962yls6f84z3kell8c5lp3pcxh".to_string(),
-                amount: coins(500, "another"),
-            },),
-            gas_limit: None,
-            reply_on: ReplyOn::Never,
-        }));
-
-        assert!(res.messages.contains(&SubMsg {
-            id: 0,
-            msg: CosmosMsg::Bank(BankMsg::Send {
-                to_address: "another".to_string(),
-                amount: coins(1500, "another"),
-            },),
-            gas_limit: None,
-            reply_on: ReplyOn::Never,
-        }));
+        // The first swap spent its input, so no other route is tried, and the input is recorded
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: 0,
+                result: SubMsgResult::Err("pool drained".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.events[0].ty, "revenue/failure");
+        assert!(!res.events[0]
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "fallback"));
+        assert!(res.messages.is_empty());
+        let res: InputStatsResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::InputStats {}).unwrap()).unwrap();
+        assert_eq!(res.inputs[0].sent, Uint128::from(100u128));
     }
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,8 +6,77 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Semver(#[from] semver::Error),
+
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Invalid Reward: bps must not exceed 10000")]
+    InvalidReward {},
+
+    #[error("Invalid Fraction: max_fraction must not exceed 1")]
+    InvalidFraction {},
+
+    #[error("Invalid Weight: weight must be at least 1")]
+    InvalidWeight {},
+
+    #[error("Invalid Batch Size: batch_size must be between 1 and {max}")]
+    InvalidBatchSize { max: u32 },
+
+    #[error("Invalid Template: {reason}")]
+    InvalidTemplate { reason: String },
+
+    #[error("Invalid Interval: the permissionless interval must be non-zero")]
+    InvalidInterval {},
+
+    #[error("Rate Limited")]
+    RateLimited {},
+
+    #[error("Paused")]
+    Paused {},
+
+    #[error("Not Paused")]
+    NotPaused {},
+
+    #[error("Action {denom} is paused")]
+    ActionPaused { denom: String },
+
+    #[error("Executor {executor} already exists")]
+    ExecutorExists { executor: String },
+
+    #[error("Executor {executor} not found")]
+    ExecutorNotFound { executor: String },
+
+    #[error("Invalid Address: {field} {address}")]
+    InvalidAddress { field: String, address: String },
+
+    #[error("Insufficient Return: expected {expected}, received {received}")]
+    InsufficientReturn { expected: Coin, received: Coin },
+
+    #[error("Invalid Contract: cannot migrate from {name}")]
+    InvalidContract { name: String },
+
+    #[error("Invalid Version: cannot migrate from {stored} to {current}")]
+    InvalidVersion { stored: String, current: String },
+
+    #[error("No Pending Owner")]
+    NoPendingOwner {},
+
+    #[error("Ownership Proposal Expired")]
+    OwnershipProposalExpired {},
+
+    #[error("Invalid Config: at least one target address is required")]
+    NoTargetAddresses {},
+
+    #[error("Invalid Config: the total weight of target addresses must be non-zero")]
+    ZeroTotalWeight {},
+
+    #[error("Invalid Config: duplicate target {target}")]
+    DuplicateTarget { target: String },
+
+    #[error("Invalid Config: target {target} not found")]
+    TargetNotFound { target: String },
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
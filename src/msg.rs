@@ -1,24 +1,124 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Binary, Uint128};
+use cosmwasm_std::{Addr, Coin, CosmosMsg, Decimal, Uint128};
+use cw_utils::{Duration, Expiration};
 use kujira::Denom;
 
-use crate::state::Action;
+use crate::state::{
+    Action, ActionFailures, ExecutorReward, Hop, InputStats, MinReturn, RunRecord, Schedule,
+    SwapMsg, TargetStats, VolumeCap,
+};
 
 #[cw_serde]
 pub struct InstantiateMsg {
-    pub owner: Addr,
-    pub executor: Addr,
+    pub owner: String,
+    pub executors: Vec<String>,
     pub target_denoms: Vec<Denom>,
-    pub target_addresses: Vec<(Addr, u8)>,
+    pub target_addresses: Vec<(String, u8)>,
+    /// The maximum number of Actions executed by a single Run, at most 10. Defaults to 1
+    pub batch_size: Option<u32>,
+}
+
+#[cw_serde]
+pub enum MigrateMsg {
+    /// Runs the storage migrations required since the stored contract version,
+    /// and overrides any Config values provided
+    Upgrade {
+        owner: Option<String>,
+        executors: Option<Vec<String>>,
+        target_denoms: Option<Vec<Denom>>,
+        target_addresses: Option<Vec<(String, u8)>>,
+        batch_size: Option<u32>,
+    },
 }
 
 #[cw_serde]
+#[allow(clippy::large_enum_variant)]
 pub enum ExecuteMsg {
-    SetOwner(Addr),
-    SetExecutor(Addr),
+    /// Proposes a new owner, who must accept ownership before `expiry`
+    ProposeOwner {
+        owner: String,
+        expiry: Option<Expiration>,
+    },
+    /// Accepts a pending ownership proposal, called by the proposed owner
+    AcceptOwner {},
+    /// Withdraws a pending ownership proposal
+    CancelOwner {},
+    AddExecutor(String),
+    RemoveExecutor(String),
+    /// Allows anyone to execute Run, at most once per interval, or restricts it to the executors
+    SetPermissionless(Option<Duration>),
+    /// Sets the reward paid from each distribution to the address executing the crank
+    SetExecutorReward(Option<ExecutorReward>),
+    /// Disables an Action once this many of its swaps fail in a row, or never
+    SetMaxFailures(Option<u32>),
+    /// Sets how Run chooses the denoms it executes
+    SetSchedule(Schedule),
     SetAction(Action),
-    UnsetAction(Denom),
-    Run {},
+    /// Removes the Action for `denom` with `priority`, or every Action for `denom`
+    UnsetAction {
+        denom: Denom,
+        priority: Option<u8>,
+    },
+    /// Stops the crank executing the Action for `denom` with `priority`, or every Action for
+    /// `denom`, keeping them stored
+    PauseAction {
+        denom: Denom,
+        priority: Option<u8>,
+    },
+    /// Re-enables paused Actions, clearing their consecutive failures
+    ResumeAction {
+        denom: Denom,
+        priority: Option<u8>,
+    },
+    /// Sets the address permitted to pause the crank, besides the owner
+    SetGuardian(Option<String>),
+    /// Stops the crank from being executed. Callable by the owner or the guardian
+    Pause {},
+    Resume {},
+    /// Sends the contract's balance of each of `denoms` to `recipient`. Only while paused
+    Sweep {
+        recipient: String,
+        denoms: Vec<Denom>,
+    },
+    /// Executes the next Actions in the rotation, up to `max_actions` or the configured
+    /// `batch_size`, whichever is lower, and distributes the target denoms.
+    /// Restricted to the executors unless the contract is permissionless
+    Run {
+        max_actions: Option<u32>,
+    },
+    /// Executes the Action for a specific denom and distributes the target denoms,
+    /// without advancing the rotation
+    RunDenom(Denom),
+    UpdateTargetDenoms(TargetDenomsUpdate),
+    UpdateTargetAddresses(TargetAddressesUpdate),
+    /// Executes the final swap of a route, then checks that the contract received at least
+    /// `min_return` since its balance was `before`, reverting the swap otherwise.
+    /// Only callable by the contract itself
+    GuardedSwap {
+        msg: CosmosMsg,
+        min_return: Coin,
+        before: Uint128,
+    },
+    /// Fails unless the contract received at least `min_return` since its balance was `before`.
+    /// Only callable by the contract itself
+    CheckReturn {
+        min_return: Coin,
+        before: Uint128,
+    },
+}
+
+#[cw_serde]
+pub enum TargetDenomsUpdate {
+    Add(Vec<Denom>),
+    Remove(Vec<Denom>),
+    Replace(Vec<Denom>),
+}
+
+#[cw_serde]
+pub enum TargetAddressesUpdate {
+    Add(Vec<(String, u8)>),
+    Remove(Vec<String>),
+    Replace(Vec<(String, u8)>),
 }
 
 #[cw_serde]
@@ -26,30 +126,152 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     #[returns(ConfigResponse)]
     Config {},
+    /// Scans up to `limit` Actions following `start_after`, listing those whose swap or hops
+    /// execute on `contract`, if any. Continue from `next_start_after` until it is `None`
+    #[returns(ActionsResponse)]
+    Actions {
+        start_after: Option<(Denom, u8)>,
+        limit: Option<u32>,
+        contract: Option<String>,
+    },
+    /// The Actions for `denom`, in order of priority
     #[returns(ActionsResponse)]
-    Actions {},
+    Action { denom: Denom },
     #[returns(StatusResponse)]
     Status {},
+    #[returns(PendingOwnerResponse)]
+    PendingOwner {},
+    #[returns(RewardsResponse)]
+    Rewards {},
+    /// Reports what the next Run would do at the current balances, without executing it
+    #[returns(SimulateRunResponse)]
+    SimulateRun {},
+    /// Reports how the current balance of each target denom, or only `denom`, would be distributed
+    #[returns(DistributionsResponse)]
+    Distribution { denom: Option<Denom> },
+    /// The cumulative amounts of each Action's denom swapped by the crank
+    #[returns(InputStatsResponse)]
+    InputStats {},
+    /// The cumulative amounts of each target denom distributed to the target addresses
+    #[returns(TargetStatsResponse)]
+    TargetStats {},
+    /// The failed swaps of each Action
+    #[returns(FailuresResponse)]
+    Failures {},
+    /// The most recent Runs, oldest first, following the `start_after` id
+    #[returns(HistoryResponse)]
+    History {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[cw_serde]
 pub struct ConfigResponse {
     pub owner: Addr,
-    pub executor: Addr,
+    pub executors: Vec<Addr>,
+    pub guardian: Option<Addr>,
+    pub permissionless: Option<Duration>,
+    pub executor_reward: Option<ExecutorReward>,
     pub target_denoms: Vec<Denom>,
     pub target_addresses: Vec<(Addr, u8)>,
+    pub batch_size: u32,
+    pub max_failures: Option<u32>,
+    pub paused: bool,
+    pub schedule: Schedule,
 }
 
 #[cw_serde]
 pub struct ActionsResponse {
     pub actions: Vec<ActionResponse>,
+    /// The key to list the following Actions from, unless all have been scanned
+    pub next_start_after: Option<(Denom, u8)>,
 }
 #[cw_serde]
 pub struct ActionResponse {
     pub denom: Denom,
     pub contract: Addr,
     pub limit: Uint128,
-    pub msg: Binary,
+    pub max_fraction: Option<Decimal>,
+    pub min_amount: Option<Uint128>,
+    pub cooldown: Option<Duration>,
+    pub volume_cap: Option<VolumeCap>,
+    pub weight: u32,
+    pub msg: SwapMsg,
+    pub min_return: Option<MinReturn>,
+    pub hops: Vec<Hop>,
+    pub enabled: bool,
+    pub priority: u8,
+}
+
+#[cw_serde]
+pub struct PendingOwnerResponse {
+    pub owner: Option<Addr>,
+    pub expiry: Option<Expiration>,
+}
+
+#[cw_serde]
+pub struct SimulateRunResponse {
+    /// Whether the crank is paused, in which case a Run would fail and nothing is listed
+    pub paused: bool,
+    /// The Actions the next Run would execute, in order
+    pub actions: Vec<SimulatedActionResponse>,
+    /// The distribution of each target denom at its current balance
+    pub distributions: Vec<DistributionResponse>,
+}
+
+#[cw_serde]
+pub struct SimulatedActionResponse {
+    pub denom: Denom,
+    pub contract: Addr,
+    /// The funds sent to the swap contract, or `None` if the Action would be skipped
+    pub funds: Option<Coin>,
+    pub min_return: Option<Coin>,
+}
+
+#[cw_serde]
+pub struct DistributionResponse {
+    pub denom: Denom,
+    pub balance: Uint128,
+    /// The amount paid to the address executing the crank
+    pub executor_reward: Uint128,
+    /// The amount sent to each target address
+    pub sends: Vec<(Addr, Uint128)>,
+    /// The target address that absorbs the rounding remainder, and the amount above its
+    /// weighted share
+    pub remainder: Option<(Addr, Uint128)>,
+}
+
+#[cw_serde]
+pub struct DistributionsResponse {
+    pub distributions: Vec<DistributionResponse>,
+}
+
+#[cw_serde]
+pub struct InputStatsResponse {
+    pub inputs: Vec<InputStats>,
+}
+
+#[cw_serde]
+pub struct TargetStatsResponse {
+    pub targets: Vec<TargetStats>,
+}
+
+#[cw_serde]
+pub struct FailuresResponse {
+    pub failures: Vec<ActionFailures>,
+}
+
+#[cw_serde]
+pub struct HistoryResponse {
+    /// The retained Runs and their ids
+    pub runs: Vec<(u64, RunRecord)>,
+}
+
+#[cw_serde]
+pub struct RewardsResponse {
+    /// The total rewards paid to executors, per denom
+    pub paid: Vec<Coin>,
 }
 
 #[cw_serde]
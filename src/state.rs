@@ -1,30 +1,91 @@
 use std::cmp::min;
 
-use crate::msg::{ActionResponse, ConfigResponse, InstantiateMsg};
+use crate::contract::validate_addr;
+use crate::msg::{ActionResponse, ConfigResponse};
+use crate::ContractError;
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{
-    coins, Addr, Binary, Coin, CosmosMsg, Order, StdError, StdResult, Storage, Uint128, WasmMsg,
+    coin, from_json, to_json_binary, Addr, Api, Binary, BlockInfo, Coin, CosmosMsg, Decimal,
+    Decimal256, Order, StdError, StdResult, Storage, Timestamp, Uint128, WasmMsg,
 };
 use cw_storage_plus::{Bound, Item, Map};
-use kujira::Denom;
+use cw_utils::{Duration, Expiration};
+use kujira::{fin, Denom, KujiraQuerier, NormalizedPrice};
+use serde::de::IgnoredAny;
 
 static CONFIG: Item<Config> = Item::new("config");
+static PENDING_OWNER: Item<PendingOwner> = Item::new("pending_owner");
 static LAST: Item<String> = Item::new("last");
-static ACTIONS: Map<String, (Addr, Uint128, Binary)> = Map::new("actions");
+static ACTIONS: Map<(String, u8), Action> = Map::new("actions");
+static PENDING: Item<Pending> = Item::new("pending");
+static LAST_RUN: Item<LastRun> = Item::new("last_run");
+static REWARDS: Map<String, Uint128> = Map::new("rewards");
+static INPUT_STATS: Map<String, InputStats> = Map::new("input_stats");
+static TARGET_STATS: Map<String, TargetStats> = Map::new("target_stats");
+static FAILURES: Map<(String, u8), ActionFailures> = Map::new("failures");
+static USAGE: Map<(String, u8), ActionUsage> = Map::new("usage");
+static PASSES: Map<String, u64> = Map::new("passes");
+static SCHEDULE: Map<(u64, String), ()> = Map::new("schedule");
+static PASS: Item<u64> = Item::new("pass");
+static HISTORY: Map<u64, RunRecord> = Map::new("history");
+static HISTORY_COUNT: Item<u64> = Item::new("history_count");
+
+/// The number of Runs retained in the history
+pub const HISTORY_SIZE: u64 = 100;
+
+/// The maximum number of Actions executed by a single Run
+pub const MAX_BATCH_SIZE: u32 = 10;
+
+/// The number of executions within its volume cap's window retained for each Action
+const USAGE_SIZE: usize = 32;
+
+/// The pass added to a denom of weight 1 each time it's scheduled by `Schedule::Weighted`
+const STRIDE: u64 = 1 << 32;
 
 #[cw_serde]
 pub struct Config {
     /// The address permitted to set Actions
     pub owner: Addr,
 
-    /// The address permitted to execute the crank
-    pub executor: Addr,
+    /// The addresses permitted to execute the crank
+    pub executors: Vec<Addr>,
+
+    /// The address permitted to pause the crank in an emergency
+    pub guardian: Option<Addr>,
+
+    /// When set, anyone may execute the crank, at most once per interval
+    pub permissionless: Option<Duration>,
+
+    /// The share of each distribution paid to the address executing the crank
+    pub executor_reward: Option<ExecutorReward>,
 
     /// The denoms that are transferred to the fee_collector at the end of every execution
     pub target_denoms: Vec<Denom>,
 
     /// The final destinations that `target_denom` is sent to (address, weight)
     pub target_addresses: Vec<(Addr, u8)>,
+
+    /// The maximum number of Actions executed by a single Run, from 1 to `MAX_BATCH_SIZE`
+    pub batch_size: u32,
+
+    /// The number of consecutive failed swaps after which an Action is disabled
+    pub max_failures: Option<u32>,
+
+    /// When set, the crank can't be executed
+    pub paused: bool,
+
+    /// How Run chooses the denoms it executes
+    pub schedule: Schedule,
+}
+
+#[cw_serde]
+pub enum Schedule {
+    /// Each denom in turn, in denom order
+    Rotation,
+    /// Each denom in proportion to the weight of its first enabled Action, so that a denom
+    /// of weight 3 is executed three times as often as one of weight 1. Ties are executed
+    /// in denom order
+    Weighted,
 }
 
 impl Config {
@@ -35,16 +96,375 @@ impl Config {
     pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
         CONFIG.save(storage, self)
     }
+
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.batch_size == 0 || self.batch_size > MAX_BATCH_SIZE {
+            return Err(ContractError::InvalidBatchSize {
+                max: MAX_BATCH_SIZE,
+            });
+        }
+        if self.target_addresses.is_empty() {
+            return Err(ContractError::NoTargetAddresses {});
+        }
+        if self.target_addresses.iter().all(|(_, weight)| *weight == 0) {
+            return Err(ContractError::ZeroTotalWeight {});
+        }
+        for (i, denom) in self.target_denoms.iter().enumerate() {
+            if self.target_denoms[..i].contains(denom) {
+                return Err(ContractError::DuplicateTarget {
+                    target: denom.to_string(),
+                });
+            }
+        }
+        for (i, (addr, _)) in self.target_addresses.iter().enumerate() {
+            if self.target_addresses[..i].iter().any(|(x, _)| x == addr) {
+                return Err(ContractError::DuplicateTarget {
+                    target: addr.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits a `balance` of `denom` into the executor's reward and the amounts sent to each
+    /// target address, the last of which also receives the rounding remainder
+    pub fn distribute(&self, denom: &Denom, balance: Uint128) -> Distribution {
+        let reward = match &self.executor_reward {
+            Some(reward) => reward.amount(denom, balance),
+            None => Uint128::zero(),
+        };
+        let balance = balance - reward;
+        let total_weight = self
+            .target_addresses
+            .iter()
+            .fold(0u32, |a, e| u32::from(e.1) + a);
+
+        let mut remaining = balance;
+        let mut sends = vec![];
+        let mut remainder = None;
+        let mut targets = self.target_addresses.iter().peekable();
+        while let Some((addr, weight)) = targets.next() {
+            let share = balance.mul_floor(Decimal::from_ratio(*weight, total_weight));
+            let amount = if targets.peek().is_none() {
+                remainder = Some((addr.clone(), remaining - share));
+                remaining
+            } else {
+                share
+            };
+            remaining -= amount;
+            sends.push((addr.clone(), amount));
+        }
+        Distribution {
+            reward,
+            sends,
+            remainder,
+        }
+    }
+}
+
+/// The split of a balance of a target denom
+pub struct Distribution {
+    /// The amount paid to the executor
+    pub reward: Uint128,
+    /// The amount sent to each target address
+    pub sends: Vec<(Addr, Uint128)>,
+    /// The target address that absorbs the rounding remainder, and the amount above its share
+    pub remainder: Option<(Addr, Uint128)>,
+}
+
+#[cw_serde]
+pub struct ExecutorReward {
+    /// The share of the balance of each target denom paid, in basis points
+    pub bps: u16,
+    /// The maximum paid per distribution of a target denom. Denoms without a cap are unlimited
+    pub caps: Vec<(Denom, Uint128)>,
+}
+
+impl ExecutorReward {
+    /// The reward paid from a distribution of `balance` of `denom`
+    pub fn amount(&self, denom: &Denom, balance: Uint128) -> Uint128 {
+        let reward = balance.mul_floor(Decimal::from_ratio(self.bps, 10000u16));
+        match self.caps.iter().find(|(x, _)| x == denom) {
+            Some((_, cap)) => min(reward, *cap),
+            None => reward,
+        }
+    }
+
+    /// Adds to the total reward paid in `denom`
+    pub fn record(storage: &mut dyn Storage, denom: &Denom, amount: Uint128) -> StdResult<()> {
+        REWARDS.update(storage, denom.to_string(), |paid| {
+            paid.unwrap_or_default()
+                .checked_add(amount)
+                .map_err(StdError::from)
+        })?;
+        Ok(())
+    }
+
+    /// The total rewards paid to executors
+    pub fn paid(storage: &dyn Storage) -> StdResult<Vec<Coin>> {
+        REWARDS
+            .range(storage, None, None, Order::Ascending)
+            .map(|res| res.map(|(denom, amount)| coin(amount.u128(), denom)))
+            .collect()
+    }
+}
+
+/// The block at which the crank was last executed
+#[cw_serde]
+pub struct LastRun {
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+impl LastRun {
+    pub fn load(storage: &dyn Storage) -> StdResult<Option<Self>> {
+        LAST_RUN.may_load(storage)
+    }
+
+    pub fn save(storage: &mut dyn Storage, block: &BlockInfo) -> StdResult<()> {
+        LAST_RUN.save(
+            storage,
+            &Self {
+                height: block.height,
+                time: block.time,
+            },
+        )
+    }
+
+    /// Whether `interval` has passed since this run. An interval too long to represent never
+    /// passes
+    pub fn elapsed(&self, block: &BlockInfo, interval: &Duration) -> bool {
+        match interval {
+            Duration::Height(height) => self
+                .height
+                .checked_add(*height)
+                .is_some_and(|end| block.height >= end),
+            Duration::Time(time) => time
+                .checked_mul(1_000_000_000)
+                .and_then(|nanos| self.time.nanos().checked_add(nanos))
+                .is_some_and(|end| block.time.nanos() >= end),
+        }
+    }
+}
+
+/// The cumulative totals of an Action's denom swapped by the crank
+#[cw_serde]
+pub struct InputStats {
+    pub denom: Denom,
+    /// The total amount sent to swap contracts
+    pub sent: Uint128,
+    /// The number of swaps that spent their input, including routes whose later hop failed
+    pub runs: u64,
+    /// The block of the latest swap
+    pub last: LastRun,
+}
+
+impl InputStats {
+    /// Records a swap that spent `amount` of `denom`, whether or not its route completed
+    pub fn record(
+        storage: &mut dyn Storage,
+        denom: &Denom,
+        amount: Uint128,
+        block: &BlockInfo,
+    ) -> StdResult<()> {
+        INPUT_STATS.update(storage, denom.to_string(), |stats| -> StdResult<_> {
+            let (sent, runs) = stats.map_or((Uint128::zero(), 0), |x| (x.sent, x.runs));
+            Ok(Self {
+                denom: denom.clone(),
+                sent: sent.checked_add(amount)?,
+                runs: runs + 1,
+                last: LastRun {
+                    height: block.height,
+                    time: block.time,
+                },
+            })
+        })?;
+        Ok(())
+    }
+
+    pub fn all(storage: &dyn Storage) -> StdResult<Vec<Self>> {
+        INPUT_STATS
+            .range(storage, None, None, Order::Ascending)
+            .map(|res| res.map(|(_, stats)| stats))
+            .collect()
+    }
+}
+
+/// The cumulative totals of a target denom distributed to the target addresses
+#[cw_serde]
+pub struct TargetStats {
+    pub denom: Denom,
+    /// The total amount sent to the target addresses
+    pub distributed: Uint128,
+    /// The total amount sent to each target address, current or former
+    pub recipients: Vec<(Addr, Uint128)>,
+}
+
+impl TargetStats {
+    /// Records the amounts of `denom` sent to each target address
+    pub fn record(
+        storage: &mut dyn Storage,
+        denom: &Denom,
+        sends: &[(Addr, Uint128)],
+    ) -> StdResult<()> {
+        if sends.iter().all(|(_, amount)| amount.is_zero()) {
+            return Ok(());
+        }
+        let mut stats = TARGET_STATS
+            .may_load(storage, denom.to_string())?
+            .unwrap_or(Self {
+                denom: denom.clone(),
+                distributed: Uint128::zero(),
+                recipients: vec![],
+            });
+        for (addr, amount) in sends.iter().filter(|(_, amount)| !amount.is_zero()) {
+            stats.distributed = stats.distributed.checked_add(*amount)?;
+            match stats.recipients.iter_mut().find(|(x, _)| x == addr) {
+                Some((_, total)) => *total = total.checked_add(*amount)?,
+                None => stats.recipients.push((addr.clone(), *amount)),
+            }
+        }
+        TARGET_STATS.save(storage, denom.to_string(), &stats)
+    }
+
+    pub fn all(storage: &dyn Storage) -> StdResult<Vec<Self>> {
+        TARGET_STATS
+            .range(storage, None, None, Order::Ascending)
+            .map(|res| res.map(|(_, stats)| stats))
+            .collect()
+    }
+}
+
+/// The swaps of an Action's route that have failed
+#[cw_serde]
+pub struct ActionFailures {
+    pub denom: Denom,
+    pub priority: u8,
+    /// The number of failures since the Action last completed a swap or was set
+    pub consecutive: u32,
+    /// The total number of failures
+    pub total: u64,
+}
+
+impl ActionFailures {
+    /// Records a failed swap of the Action
+    pub fn record(storage: &mut dyn Storage, action: &Action) -> StdResult<Self> {
+        let key = (action.denom.to_string(), action.priority);
+        FAILURES.update(storage, key, |failures| -> StdResult<_> {
+            let (consecutive, total) = failures.map_or((0, 0), |x| (x.consecutive, x.total));
+            Ok(Self {
+                denom: action.denom.clone(),
+                priority: action.priority,
+                consecutive: consecutive + 1,
+                total: total + 1,
+            })
+        })
+    }
+
+    /// Clears the consecutive failures of the Action
+    pub fn reset(storage: &mut dyn Storage, action: &Action) -> StdResult<()> {
+        let key = (action.denom.to_string(), action.priority);
+        if let Some(mut failures) = FAILURES.may_load(storage, key.clone())? {
+            failures.consecutive = 0;
+            FAILURES.save(storage, key, &failures)?;
+        }
+        Ok(())
+    }
+
+    pub fn all(storage: &dyn Storage) -> StdResult<Vec<Self>> {
+        FAILURES
+            .range(storage, None, None, Order::Ascending)
+            .map(|res| res.map(|(_, failures)| failures))
+            .collect()
+    }
 }
 
-impl From<InstantiateMsg> for Config {
-    fn from(value: InstantiateMsg) -> Self {
+/// A single execution of the crank
+#[cw_serde]
+pub struct RunRecord {
+    pub height: u64,
+    pub time: Timestamp,
+    /// The address that executed the crank
+    pub executor: Addr,
+    /// The Actions swapped, in order
+    pub swaps: Vec<SwapRecord>,
+    /// The balance of each target denom distributed, including the executor's reward
+    pub distributions: Vec<Coin>,
+}
+
+/// A swap completed during a Run
+#[cw_serde]
+pub struct SwapRecord {
+    pub denom: Denom,
+    pub contract: Addr,
+    /// The amount sent to the swap contract
+    pub amount: Uint128,
+    /// The increase in the contract's balance of each target denom across the route
+    pub output: Vec<Coin>,
+    /// The error returned by the route, if it failed
+    pub error: Option<String>,
+}
+
+impl RunRecord {
+    pub fn new(block: &BlockInfo, executor: Addr) -> Self {
         Self {
-            owner: value.owner,
-            executor: value.executor,
-            target_denoms: value.target_denoms,
-            target_addresses: value.target_addresses,
+            height: block.height,
+            time: block.time,
+            executor,
+            swaps: vec![],
+            distributions: vec![],
+        }
+    }
+
+    /// Appends the Run to the history, evicting the oldest entry once it is full
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        let id = HISTORY_COUNT.may_load(storage)?.unwrap_or_default();
+        HISTORY.save(storage, id, self)?;
+        if id >= HISTORY_SIZE {
+            HISTORY.remove(storage, id - HISTORY_SIZE);
         }
+        HISTORY_COUNT.save(storage, &(id + 1))
+    }
+
+    /// Loads up to `limit` of the retained Runs, oldest first, following the `start_after` id
+    pub fn history(
+        storage: &dyn Storage,
+        start_after: Option<u64>,
+        limit: usize,
+    ) -> StdResult<Vec<(u64, Self)>> {
+        HISTORY
+            .range(
+                storage,
+                start_after.map(Bound::exclusive),
+                None,
+                Order::Ascending,
+            )
+            .take(limit)
+            .collect()
+    }
+}
+
+/// An ownership transfer proposed by the owner, awaiting acceptance by the nominee
+#[cw_serde]
+pub struct PendingOwner {
+    /// The proposed owner
+    pub owner: Addr,
+
+    /// When the proposal lapses, if ever
+    pub expiry: Option<Expiration>,
+}
+
+impl PendingOwner {
+    pub fn load(storage: &dyn Storage) -> StdResult<Option<Self>> {
+        PENDING_OWNER.may_load(storage)
+    }
+
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        PENDING_OWNER.save(storage, self)
+    }
+
+    pub fn clear(storage: &mut dyn Storage) {
+        PENDING_OWNER.remove(storage)
     }
 }
 
@@ -52,9 +472,16 @@ impl From<Config> for ConfigResponse {
     fn from(value: Config) -> Self {
         Self {
             owner: value.owner,
-            executor: value.executor,
+            executors: value.executors,
+            guardian: value.guardian,
+            permissionless: value.permissionless,
+            executor_reward: value.executor_reward,
             target_denoms: value.target_denoms,
             target_addresses: value.target_addresses,
+            batch_size: value.batch_size,
+            max_failures: value.max_failures,
+            paused: value.paused,
+            schedule: value.schedule,
         }
     }
 }
@@ -64,11 +491,303 @@ pub struct Action {
     /// Token denom
     pub denom: Denom,
     /// The target contract for swapping
-    pub contract: Addr,
+    pub contract: String,
     /// The maximum amount of the token that can be included in any one execution of the Action
     pub limit: Uint128,
+    /// The maximum share of the contract's balance of the token included in any one execution
+    #[serde(default)]
+    pub max_fraction: Option<Decimal>,
+    /// The amount below which the Action is skipped, rather than swapping dust
+    #[serde(default)]
+    pub min_amount: Option<Uint128>,
+    /// The minimum interval between executions of the Action
+    #[serde(default)]
+    pub cooldown: Option<Duration>,
+    /// The maximum amount of the token swapped by the Action within a window
+    #[serde(default)]
+    pub volume_cap: Option<VolumeCap>,
+    /// How often the denom is executed relative to others under `Schedule::Weighted`
+    #[serde(default = "weight")]
+    pub weight: u32,
     /// The msg executed on the contract to swap to the target token
-    pub msg: Binary,
+    pub msg: SwapMsg,
+    /// The minimum amount of the target token that must be returned by the swap, or by the
+    /// final hop of the route
+    pub min_return: Option<MinReturn>,
+    /// Subsequent swaps executed in order, each offering the output of the previous one
+    #[serde(default)]
+    pub hops: Vec<Hop>,
+    /// Whether the crank executes the Action
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// The order in which the Actions for the same denom are tried, lowest first. Each is a
+    /// fallback for the ones before it, executed if their swap fails
+    #[serde(default)]
+    pub priority: u8,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn weight() -> u32 {
+    1
+}
+
+/// A cap on the amount of an Action's token swapped within a window
+#[cw_serde]
+pub struct VolumeCap {
+    pub amount: Uint128,
+    /// The length of the rolling window preceding each execution
+    pub window: Duration,
+}
+
+/// The recent executions of an Action, limited by its cooldown and volume cap
+#[cw_serde]
+pub struct ActionUsage {
+    /// The block of the latest execution
+    pub last: LastRun,
+    /// The block and amount swapped of each execution within the volume cap's window, oldest
+    /// first. Beyond `USAGE_SIZE`, the oldest two are merged at the later block, which keeps
+    /// their amounts in the window for longer rather than releasing them early
+    pub volumes: Vec<(LastRun, Uint128)>,
+}
+
+impl ActionUsage {
+    /// Records a completed swap of `amount` by the Action
+    pub fn record(
+        storage: &mut dyn Storage,
+        action: &Action,
+        amount: Uint128,
+        block: &BlockInfo,
+    ) -> StdResult<()> {
+        let key = (action.denom.to_string(), action.priority);
+        let last = LastRun {
+            height: block.height,
+            time: block.time,
+        };
+        let mut volumes = vec![];
+        if let Some(cap) = &action.volume_cap {
+            if let Some(usage) = USAGE.may_load(storage, key.clone())? {
+                volumes = usage.window(block, &cap.window);
+            }
+            volumes.push((last.clone(), amount));
+            if volumes.len() > USAGE_SIZE {
+                let (_, oldest) = volumes.remove(0);
+                volumes[0].1 = volumes[0].1.checked_add(oldest)?;
+            }
+        }
+        USAGE.save(storage, key, &Self { last, volumes })
+    }
+
+    /// The amount the Action may swap at `block`, which is zero during its cooldown
+    pub fn allowance(
+        storage: &dyn Storage,
+        action: &Action,
+        block: &BlockInfo,
+    ) -> StdResult<Uint128> {
+        let cap = action
+            .volume_cap
+            .as_ref()
+            .map_or(Uint128::MAX, |cap| cap.amount);
+        let usage = match USAGE.may_load(storage, (action.denom.to_string(), action.priority))? {
+            Some(usage) => usage,
+            None => return Ok(cap),
+        };
+        if action
+            .cooldown
+            .is_some_and(|cooldown| !usage.last.elapsed(block, &cooldown))
+        {
+            return Ok(Uint128::zero());
+        }
+        match &action.volume_cap {
+            Some(volume_cap) => {
+                let volume = usage
+                    .window(block, &volume_cap.window)
+                    .into_iter()
+                    .fold(Uint128::zero(), |a, (_, amount)| a + amount);
+                Ok(cap.saturating_sub(volume))
+            }
+            None => Ok(cap),
+        }
+    }
+
+    /// The executions within the `window` preceding `block`
+    fn window(self, block: &BlockInfo, window: &Duration) -> Vec<(LastRun, Uint128)> {
+        self.volumes
+            .into_iter()
+            .filter(|(at, _)| !at.elapsed(block, window))
+            .collect()
+    }
+}
+
+#[cw_serde]
+pub struct Hop {
+    /// The token denom returned by the previous swap, and offered to this one
+    pub denom: Denom,
+    /// The target contract for swapping
+    pub contract: String,
+    /// The msg executed on the contract to swap to the next token
+    pub msg: SwapMsg,
+}
+
+impl Hop {
+    /// Builds the swap msg offering `amount`. `sender` is the address of this contract
+    pub fn execute(&self, sender: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+        let offer = coin(amount.u128(), self.denom.to_string());
+        let msg = self.msg.render(&offer, sender, || {
+            Err(StdError::generic_err(
+                "Invalid Template: belief_price is only available to the first swap of a route",
+            ))
+        })?;
+        Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: self.contract.clone(),
+            msg,
+            funds: vec![offer],
+        }))
+    }
+}
+
+/// The msg executed on a swap contract
+#[cw_serde]
+pub enum SwapMsg {
+    /// A market swap on a FIN pair
+    Fin {
+        belief_price: Option<Decimal256>,
+        max_spread: Option<Decimal256>,
+    },
+    /// A msg executed as provided
+    Raw(Binary),
+    /// A JSON msg with the placeholders `{{offer_amount}}`, `{{offer_denom}}`, `{{contract}}`
+    /// (this contract's address) and `{{belief_price}}` substituted when it is executed
+    Template(String),
+}
+
+impl SwapMsg {
+    /// Serializes the msg for a swap of `offer` by `sender`, calling `price` for the belief
+    /// price if a template requires it
+    pub fn render(
+        &self,
+        offer: &Coin,
+        sender: &Addr,
+        price: impl FnOnce() -> StdResult<Decimal>,
+    ) -> StdResult<Binary> {
+        match self {
+            SwapMsg::Fin {
+                belief_price,
+                max_spread,
+            } => to_json_binary(&fin::ExecuteMsg::Swap {
+                offer_asset: None,
+                belief_price: *belief_price,
+                max_spread: *max_spread,
+                to: None,
+                callback: None,
+            }),
+            SwapMsg::Raw(msg) => Ok(msg.clone()),
+            SwapMsg::Template(template) => {
+                let mut msg = template
+                    .replace("{{offer_amount}}", &offer.amount.to_string())
+                    .replace("{{offer_denom}}", &offer.denom)
+                    .replace("{{contract}}", sender.as_str());
+                if msg.contains("{{belief_price}}") {
+                    msg = msg.replace("{{belief_price}}", &price()?.to_string());
+                }
+                Ok(Binary::from(msg.into_bytes()))
+            }
+        }
+    }
+
+    /// Checks that a template renders to JSON, and only requires the belief price if it's
+    /// `priced`
+    pub fn validate(&self, priced: bool) -> Result<(), ContractError> {
+        let SwapMsg::Template(template) = self else {
+            return Ok(());
+        };
+        if !priced && template.contains("{{belief_price}}") {
+            return Err(ContractError::InvalidTemplate {
+                reason: "belief_price is only available to the first swap of a route with an \
+                         Oracle min_return"
+                    .to_string(),
+            });
+        }
+        let msg = self.render(&coin(1, "denom"), &Addr::unchecked("contract"), || {
+            Ok(Decimal::one())
+        })?;
+        from_json::<IgnoredAny>(&msg).map_err(|err| ContractError::InvalidTemplate {
+            reason: err.to_string(),
+        })?;
+        Ok(())
+    }
+}
+
+#[cw_serde]
+pub enum MinReturn {
+    /// A fixed amount of `denom` that must be received
+    Amount { denom: Denom, amount: Uint128 },
+    /// A floor on the amount of `denom` received per unit of the token swapped
+    Price { denom: Denom, price: Decimal },
+    /// A floor derived from the oracle prices of the token swapped and `denom`,
+    /// allowing the swap to return at most `max_slippage` basis points less than the oracle rate
+    Oracle {
+        denom: Denom,
+        max_slippage: u16,
+        offer: OracleAsset,
+        ask: OracleAsset,
+    },
+}
+
+/// The oracle symbol and decimal places used to price a denom
+#[cw_serde]
+pub struct OracleAsset {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl OracleAsset {
+    /// The oracle price of the asset, which must be non-zero
+    pub fn price(&self, querier: &KujiraQuerier) -> StdResult<NormalizedPrice> {
+        let price = NormalizedPrice::from_oracle(querier, self.symbol.clone(), self.decimals)?;
+        if price.inner().is_zero() {
+            return Err(StdError::generic_err(format!(
+                "Invalid Oracle Price: {} is zero",
+                self.symbol
+            )));
+        }
+        Ok(price)
+    }
+
+    /// The amount of `ask` worth one unit of `offer`, at the oracle prices
+    pub fn rate(querier: &KujiraQuerier, offer: &Self, ask: &Self) -> StdResult<Decimal> {
+        offer
+            .price(querier)?
+            .inner()
+            .checked_div(ask.price(querier)?.inner())
+            .map_err(|_| StdError::generic_err("Invalid Oracle Price"))
+    }
+}
+
+impl MinReturn {
+    /// The minimum return for a swap of `offer` tokens
+    pub fn amount(&self, querier: &KujiraQuerier, offer: Uint128) -> StdResult<Coin> {
+        match self {
+            MinReturn::Amount { denom, amount } => Ok(coin(amount.u128(), denom.to_string())),
+            MinReturn::Price { denom, price } => {
+                Ok(coin(offer.mul_ceil(*price).u128(), denom.to_string()))
+            }
+            MinReturn::Oracle {
+                denom,
+                max_slippage,
+                offer: offer_asset,
+                ask: ask_asset,
+            } => {
+                let rate = OracleAsset::rate(querier, offer_asset, ask_asset)?;
+                let tolerance =
+                    Decimal::from_ratio(10000u16.saturating_sub(*max_slippage), 10000u16);
+                let expected = offer.mul_floor(rate).mul_floor(tolerance);
+                Ok(coin(expected.u128(), denom.to_string()))
+            }
+        }
+    }
 }
 
 impl Action {
@@ -76,78 +795,291 @@ impl Action {
         LAST.may_load(storage)
     }
 
-    pub fn next(storage: &mut dyn Storage) -> StdResult<Option<Self>> {
-        let min = LAST.may_load(storage)?.map(Bound::exclusive);
-        match ACTIONS
-            .range(storage, min, None, Order::Ascending)
-            .take(1)
-            .collect::<StdResult<Vec<(String, (Addr, Uint128, Binary))>>>()?
-            .first()
-        {
-            Some(res) => Ok(Some(Self::load(storage, res)?)),
-            // If there's nothing next, try the start
-            None => {
-                if let Some(res) = ACTIONS.first(storage)? {
-                    return Ok(Some(Self::load(storage, &res)?));
+    /// Loads the first enabled Action of up to `limit` denoms, chosen by the `schedule`
+    pub fn peek(storage: &dyn Storage, limit: usize, schedule: &Schedule) -> StdResult<Vec<Self>> {
+        match schedule {
+            Schedule::Rotation => Self::rotation(storage, limit),
+            Schedule::Weighted => Ok(Self::weighted(storage, limit)?
+                .into_iter()
+                .map(|(action, _)| action)
+                .collect()),
+        }
+    }
+
+    /// Takes the first enabled Action of up to `limit` denoms, chosen by the `schedule`, and
+    /// advances the schedule past them
+    pub fn next(
+        storage: &mut dyn Storage,
+        limit: usize,
+        schedule: &Schedule,
+    ) -> StdResult<Vec<Self>> {
+        let actions = match schedule {
+            Schedule::Rotation => Self::rotation(storage, limit)?,
+            Schedule::Weighted => {
+                let scheduled = Self::weighted(storage, limit)?;
+                for (action, pass) in &scheduled {
+                    PASS.save(storage, pass)?;
+                    let stride = STRIDE / u64::from(action.weight);
+                    Self::reschedule(storage, &action.denom, pass + stride)?;
                 }
-                Ok(None)
+                scheduled.into_iter().map(|(action, _)| action).collect()
             }
+        };
+        if let Some(action) = actions.last() {
+            LAST.save(storage, &action.denom.to_string())?;
         }
+        Ok(actions)
     }
 
-    fn load(
-        storage: &mut dyn Storage,
-        (denom, (contract, limit, msg)): &(String, (Addr, Uint128, Binary)),
-    ) -> StdResult<Self> {
-        LAST.save(storage, denom)?;
-        Ok(Self {
-            denom: Denom::from(denom),
-            contract: contract.clone(),
-            limit: *limit,
-            msg: msg.clone(),
-        })
+    /// The first enabled Action of up to `limit` denoms following the last one executed,
+    /// wrapping around to the start
+    fn rotation(storage: &dyn Storage, limit: usize) -> StdResult<Vec<Self>> {
+        let last = LAST.may_load(storage)?;
+        let next = ACTIONS.range(
+            storage,
+            last.clone().map(|x| Bound::exclusive((x, u8::MAX))),
+            None,
+            Order::Ascending,
+        );
+        // If there's nothing next, continue from the start
+        let start = ACTIONS.range(
+            storage,
+            None,
+            last.map(|x| Bound::inclusive((x, u8::MAX))),
+            Order::Ascending,
+        );
+
+        let mut actions: Vec<Self> = vec![];
+        for res in next.chain(start) {
+            let (_, action) = res?;
+            if !action.enabled || actions.last().is_some_and(|x| x.denom == action.denom) {
+                continue;
+            }
+            if actions.len() == limit {
+                break;
+            }
+            actions.push(action);
+        }
+        Ok(actions)
     }
 
-    pub fn all(storage: &dyn Storage) -> StdResult<Vec<Self>> {
-        ACTIONS
+    /// The first enabled Action of the `limit` denoms with the lowest pass, along with it.
+    /// Each scheduled denom's pass grows inversely to its weight, and a denom that hasn't
+    /// been scheduled recently starts from the pass of the latest one, rather than catching up
+    fn weighted(storage: &dyn Storage, limit: usize) -> StdResult<Vec<(Self, u64)>> {
+        let current = PASS.may_load(storage)?.unwrap_or_default();
+        let mut actions: Vec<(Self, u64)> = vec![];
+        for res in SCHEDULE.keys(storage, None, None, Order::Ascending) {
+            if actions.len() == limit {
+                break;
+            }
+            let (pass, denom) = res?;
+            let action = ACTIONS
+                .prefix(denom)
+                .range(storage, None, None, Order::Ascending)
+                .map(|res| res.map(|(_, action)| action))
+                .find(|res| res.as_ref().map_or(true, |action| action.enabled))
+                .transpose()?;
+            if let Some(action) = action {
+                actions.push((action, pass.max(current)));
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Moves `denom` to `pass` in the weighted schedule
+    fn reschedule(storage: &mut dyn Storage, denom: &Denom, pass: u64) -> StdResult<()> {
+        if let Some(previous) = PASSES.may_load(storage, denom.to_string())? {
+            SCHEDULE.remove(storage, (previous, denom.to_string()));
+        }
+        PASSES.save(storage, denom.to_string(), &pass)?;
+        SCHEDULE.save(storage, (pass, denom.to_string()), &())
+    }
+
+    pub fn load(storage: &dyn Storage, denom: &Denom, priority: u8) -> StdResult<Self> {
+        ACTIONS.load(storage, (denom.to_string(), priority))
+    }
+
+    /// Loads the Actions for `denom` in order of priority, or only the one with `priority`
+    pub fn select(
+        storage: &dyn Storage,
+        denom: &Denom,
+        priority: Option<u8>,
+    ) -> StdResult<Vec<Self>> {
+        if let Some(priority) = priority {
+            return Ok(vec![Self::load(storage, denom, priority)?]);
+        }
+        let actions = ACTIONS
+            .prefix(denom.to_string())
             .range(storage, None, None, Order::Ascending)
-            .map(|res| match res {
-                Ok((denom, (contract, limit, msg))) => Ok(Self {
-                    denom: Denom::from(denom),
-                    contract,
-                    limit,
-                    msg,
-                }),
-                Err(err) => Err(err),
+            .map(|res| res.map(|(_, action)| action))
+            .collect::<StdResult<Vec<Self>>>()?;
+        if actions.is_empty() {
+            return Err(StdError::not_found(format!("Action {denom}")));
+        }
+        Ok(actions)
+    }
+
+    /// The next enabled Action for the same denom, tried if this one fails
+    pub fn fallback(&self, storage: &dyn Storage) -> StdResult<Option<Self>> {
+        ACTIONS
+            .prefix(self.denom.to_string())
+            .range(
+                storage,
+                Some(Bound::exclusive(self.priority)),
+                None,
+                Order::Ascending,
+            )
+            .map(|res| res.map(|(_, action)| action))
+            .find(|res| res.as_ref().map_or(true, |action| action.enabled))
+            .transpose()
+    }
+
+    /// Scans up to `limit` Actions following `start_after`, returning those routed through
+    /// `contract`, if any, and the key to continue from, unless the end has been reached
+    #[allow(clippy::type_complexity)]
+    pub fn list(
+        storage: &dyn Storage,
+        start_after: Option<(Denom, u8)>,
+        limit: usize,
+        contract: Option<&str>,
+    ) -> StdResult<(Vec<Self>, Option<(Denom, u8)>)> {
+        let scanned = ACTIONS
+            .range(
+                storage,
+                start_after
+                    .map(|(denom, priority)| Bound::exclusive((denom.to_string(), priority))),
+                None,
+                Order::Ascending,
+            )
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?;
+        let next = match scanned.last() {
+            Some(((denom, priority), _)) if scanned.len() == limit => {
+                Some((Denom::from(denom.as_str()), *priority))
+            }
+            _ => None,
+        };
+        let actions = scanned
+            .into_iter()
+            .map(|(_, action)| action)
+            .filter(|action| match contract {
+                Some(contract) => action.routes(contract),
+                None => true,
             })
-            .collect()
+            .collect();
+        Ok((actions, next))
+    }
+
+    /// Checks that the swap msgs of the route can be rendered when the Action is executed
+    pub fn validate_msgs(&self) -> Result<(), ContractError> {
+        let priced = matches!(self.min_return, Some(MinReturn::Oracle { .. }));
+        self.msg.validate(priced)?;
+        for hop in self.hops.iter() {
+            hop.msg.validate(false)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the Action's swap, or any of its hops, executes on `contract`
+    pub fn routes(&self, contract: &str) -> bool {
+        self.contract == contract || self.hops.iter().any(|hop| hop.contract == contract)
     }
 
+    /// Saves the Action, updating the weighted schedule for its denom
     pub fn set(storage: &mut dyn Storage, action: Self) -> StdResult<()> {
         ACTIONS.save(
             storage,
-            action.denom.to_string(),
-            &(action.contract, action.limit, action.msg),
-        )
+            (action.denom.to_string(), action.priority),
+            &action,
+        )?;
+        Self::sync(storage, &action.denom)
     }
 
-    pub fn unset(storage: &mut dyn Storage, denom: Denom) {
-        ACTIONS.remove(storage, denom.to_string())
+    /// Removes the Action, updating the weighted schedule for its denom
+    pub fn unset(storage: &mut dyn Storage, action: &Self) -> StdResult<()> {
+        ACTIONS.remove(storage, (action.denom.to_string(), action.priority));
+        Self::sync(storage, &action.denom)
     }
 
-    pub fn execute(&self, amount: Coin) -> StdResult<Option<CosmosMsg>> {
+    /// Keeps `denom` in the weighted schedule only while it has an enabled Action, so that
+    /// Runs don't read past paused denoms. A denom that is added or resumed is scheduled at
+    /// the current pass
+    fn sync(storage: &mut dyn Storage, denom: &Denom) -> StdResult<()> {
+        let enabled = ACTIONS
+            .prefix(denom.to_string())
+            .range(storage, None, None, Order::Ascending)
+            .map(|res| res.map(|(_, action)| action.enabled))
+            .find(|res| res.as_ref().map_or(true, |enabled| *enabled))
+            .transpose()?
+            .is_some();
+        let scheduled = PASSES.may_load(storage, denom.to_string())?;
+        match (enabled, scheduled) {
+            (true, None) => {
+                let pass = PASS.may_load(storage)?.unwrap_or_default();
+                Self::reschedule(storage, denom, pass)
+            }
+            (false, Some(pass)) => {
+                SCHEDULE.remove(storage, (pass, denom.to_string()));
+                PASSES.remove(storage, denom.to_string());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The amount of the token swapped from the contract's `balance`, given the `allowance`
+    /// left by the Action's cooldown and volume cap
+    pub fn offer(&self, balance: Uint128, allowance: Uint128) -> Uint128 {
+        let mut amount = min(min(balance, self.limit), allowance);
+        if let Some(fraction) = self.max_fraction {
+            amount = min(amount, balance.mul_floor(fraction));
+        }
+        if self.min_amount.is_some_and(|x| amount < x) {
+            return Uint128::zero();
+        }
+        amount
+    }
+
+    /// Builds the swap msg for the available `amount`, along with the minimum return
+    /// expected from it, if the Action has one. `sender` is the address of this contract
+    pub fn execute(
+        &self,
+        querier: &KujiraQuerier,
+        sender: &Addr,
+        amount: Coin,
+        allowance: Uint128,
+    ) -> StdResult<Option<(CosmosMsg, Option<Coin>)>> {
         if amount.denom != self.denom.to_string() {
             return Err(StdError::generic_err("Invalid Denom"));
         }
-        let total = min(amount.amount, self.limit);
+        let total = self.offer(amount.amount, allowance);
         if total.is_zero() {
             return Ok(None);
         }
-        Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: self.contract.to_string(),
-            msg: self.msg.clone(),
-            funds: coins(total.u128(), amount.denom),
-        })))
+        let offer = coin(total.u128(), amount.denom);
+        let msg = CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: self.contract.clone(),
+            msg: self
+                .msg
+                .render(&offer, sender, || self.belief_price(querier))?,
+            funds: vec![offer],
+        });
+        let min_return = match &self.min_return {
+            Some(min_return) => Some(min_return.amount(querier, total)?),
+            None => None,
+        };
+        Ok(Some((msg, min_return)))
+    }
+
+    /// The oracle rate of the Oracle `min_return`, substituted into templates
+    fn belief_price(&self, querier: &KujiraQuerier) -> StdResult<Decimal> {
+        match &self.min_return {
+            Some(MinReturn::Oracle { offer, ask, .. }) => OracleAsset::rate(querier, offer, ask),
+            _ => Err(StdError::generic_err(
+                "Invalid Template: belief_price requires an Oracle min_return",
+            )),
+        }
     }
 }
 
@@ -155,9 +1087,126 @@ impl From<Action> for ActionResponse {
     fn from(value: Action) -> Self {
         Self {
             denom: value.denom,
-            contract: value.contract,
+            contract: Addr::unchecked(value.contract),
             limit: value.limit,
+            max_fraction: value.max_fraction,
+            min_amount: value.min_amount,
+            cooldown: value.cooldown,
+            volume_cap: value.volume_cap,
+            weight: value.weight,
             msg: value.msg,
+            min_return: value.min_return,
+            hops: value.hops,
+            enabled: value.enabled,
+            priority: value.priority,
         }
     }
 }
+
+/// The state of an Action's route that has been dispatched and is awaiting its reply
+#[cw_serde]
+pub struct Pending {
+    /// The Action being executed
+    pub action: Action,
+    /// The amount of the Action's denom sent to the first swap
+    pub offer: Uint128,
+    /// The index of the next hop of the route to be executed
+    pub hop: usize,
+    /// The contract balance of the next hop's denom before the previous swap
+    pub balance: Uint128,
+    /// The minimum return of the route, and the contract balance of its denom before the route
+    pub min_return: Option<(Coin, Uint128)>,
+    /// The contract balance of each target denom before the route
+    pub outputs: Vec<Coin>,
+    /// The Actions of the batch still to be executed once this one is complete
+    pub queue: Vec<Action>,
+    /// The Run being executed, including the address rewarded on distribution
+    pub run: RunRecord,
+}
+
+impl Pending {
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        PENDING.save(storage, self)
+    }
+
+    /// Loads and clears the pending swap
+    pub fn take(storage: &mut dyn Storage) -> StdResult<Option<Self>> {
+        let pending = PENDING.may_load(storage)?;
+        PENDING.remove(storage);
+        Ok(pending)
+    }
+}
+
+/// The storage layout written by v0.1
+mod v0_1 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{Addr, Binary, Uint128};
+    use cw_storage_plus::{Item, Map};
+    use kujira::Denom;
+
+    pub static CONFIG: Item<Config> = Item::new("config");
+    pub static ACTIONS: Map<String, (Addr, Uint128, Binary)> = Map::new("actions");
+
+    #[cw_serde]
+    pub struct Config {
+        pub owner: Addr,
+        pub executor: Addr,
+        pub target_denoms: Vec<Denom>,
+        pub target_addresses: Vec<(Addr, u8)>,
+    }
+}
+
+/// Rewrites the Config and the tuple-valued Actions stored by v0.1 into their current form
+pub fn migrate_v0_1(storage: &mut dyn Storage, api: &dyn Api) -> Result<(), ContractError> {
+    let config = v0_1::CONFIG.load(storage)?;
+    let mut target_addresses = vec![];
+    for (addr, weight) in config.target_addresses {
+        target_addresses.push((
+            validate_addr(api, "target_addresses", addr.as_str())?,
+            weight,
+        ));
+    }
+    CONFIG.save(
+        storage,
+        &Config {
+            owner: validate_addr(api, "owner", config.owner.as_str())?,
+            executors: vec![validate_addr(api, "executors", config.executor.as_str())?],
+            guardian: None,
+            permissionless: None,
+            executor_reward: None,
+            target_denoms: config.target_denoms,
+            target_addresses,
+            batch_size: 1,
+            max_failures: None,
+            paused: false,
+            schedule: Schedule::Rotation,
+        },
+    )?;
+
+    let actions = v0_1::ACTIONS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (denom, (contract, limit, msg)) in actions {
+        let field = format!("actions[{denom}].contract");
+        let contract = validate_addr(api, &field, contract.as_str())?;
+        // The legacy entries are keyed by denom alone
+        v0_1::ACTIONS.remove(storage, denom.clone());
+        let action = Action {
+            denom: Denom::from(denom),
+            contract: contract.to_string(),
+            limit,
+            max_fraction: None,
+            min_amount: None,
+            cooldown: None,
+            volume_cap: None,
+            weight: 1,
+            msg: SwapMsg::Raw(msg),
+            min_return: None,
+            hops: vec![],
+            enabled: true,
+            priority: 0,
+        };
+        Action::set(storage, action)?;
+    }
+    Ok(())
+}